@@ -0,0 +1,35 @@
+use crate::{api::task::DeleteTaskRequest, error::error_response, AppState};
+use actix_web::{delete, web, Error, HttpResponse};
+use tonic::{Request, Status};
+
+/// Deletes a task and its archive, stopping the task first if necessary.
+pub async fn delete(task_id: String, data: &AppState) -> Result<(), Status> {
+    let mut client = data.task_client.clone();
+    client.delete_task(Request::new(DeleteTaskRequest { task_id })).await?;
+    Ok(())
+}
+
+/// Delete a task and its archive.
+///
+/// This endpoint removes a task, its archive and its progress from the task service.
+/// A queued or running task is stopped first. If it does not stop in time, nothing is
+/// deleted and `409 Conflict` is returned, the request can then be repeated.
+#[utoipa::path(
+    path = "/api/v1/tasks/{taskId}",
+    params(
+        ("taskId" = String, Path, description = "Task ID of the task to be deleted")
+    ),
+    responses(
+        (status = 204, description = "Task and archive deleted"),
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 409, description = "The task is still stopping", body = ErrorResponse),
+        (status = 500, description = "Failed to delete the task", body = ErrorResponse)
+    )
+)]
+#[delete("/tasks/{taskId}")]
+pub async fn delete_task(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    match delete(path.into_inner(), &data).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(error_response(e)),
+    }
+}
@@ -1,22 +1,78 @@
 use crate::{
-    api::task::{EnqueueTaskRequest, FileInfo},
-    error::ErrorResponse,
+    api::task::{
+        enqueue_task_chunk::Payload, ArchiveFormat, Compression, CompressionMethod, DuplicatePolicy, Encryption, EnqueueTaskChunk, EnqueueTaskRequest,
+        FileInfo, TaskIdResponse as ProtoTaskIdResponse,
+    },
+    error::{error_response, ErrorResponse},
     AppState,
 };
 use actix_multipart::form::{tempfile::TempFile, text::Text, MultipartForm};
 use actix_web::{post, web, Error, HttpResponse};
-use std::io::Read;
-use tonic::Request;
+use futures::{future::join, TryFutureExt};
+use serde::Deserialize;
+use std::{collections::HashMap, io, path::Path};
+use tokio::{fs::File, io::AsyncReadExt, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use utoipa::ToSchema;
 
+/// Size of the file data chunks streamed to the task service
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
 #[derive(Debug, MultipartForm, ToSchema)]
 pub struct ArchiveForm {
     /// The name of the archive to be created
     #[schema(value_type = String)]
     archive_name: Text<String>,
-    /// List of files to be included in the archive
+    /// The archive format: zip (default), 7z, tar, tar.gz, tar.zst or tar.xz
+    #[schema(value_type = Option<String>)]
+    format: Option<Text<String>>,
+    /// The encryption: none, aes-128, aes-192, aes-256 or zipcrypto. Zip supports all of them, 7z only none and aes-256,
+    /// tarballs only none. Defaults to aes-256 for zip and 7z and to none for tarballs
+    #[schema(value_type = Option<String>)]
+    encryption: Option<Text<String>>,
+    /// The password of the encrypted archive, it has to satisfy the password policy of the service.
+    /// A password is generated when omitted
+    #[schema(value_type = Option<String>)]
+    password: Option<Text<String>>,
+    /// The compression of zip entries: stored, deflated (default), bzip2 or zstd.
+    /// Files that are compressed already, such as images, videos and archives, are always stored
+    #[schema(value_type = Option<String>)]
+    compression_method: Option<Text<String>>,
+    /// The compression level, the default level of the method is used when omitted
+    #[schema(value_type = Option<i32>)]
+    compression_level: Option<Text<i32>>,
+    /// What to do with files that have the same path: rename (default) adds a ` (1)` suffix to later files,
+    /// error rejects the task, overwrite-last keeps the last file and keep-first the first one
+    #[schema(value_type = Option<String>)]
+    duplicates: Option<Text<String>>,
+    /// JSON object overriding the compression of single files by their path in the archive,
+    /// e.g. `{"report.pdf": {"method": "stored"}, "data.csv": {"method": "zstd", "level": 19}}`
+    #[schema(value_type = Option<String>)]
+    compression_overrides: Option<Text<String>>,
+    /// JSON object with the modification time in seconds since the Unix epoch and the octal permissions
+    /// of files by their path in the archive, e.g. `{"run.sh": {"modified_time": 1700000000, "mode": "755"}}`.
+    /// Files default to the time the archive is created and to 644
+    #[schema(value_type = Option<String>)]
+    metadata: Option<Text<String>>,
+    /// Builds the same archive from the same files: entries are sorted by path, their times are set to
+    /// `source_date_epoch` and their permissions to 755 or 644. Encrypted archives still differ
+    #[schema(value_type = Option<bool>)]
+    reproducible: Option<Text<bool>>,
+    /// The time of the entries of reproducible archives in seconds since the Unix epoch, defaults to 1980-01-01
+    #[schema(value_type = Option<i64>)]
+    source_date_epoch: Option<Text<i64>>,
+    /// Seconds the archive is kept once it is completed, up to the maximum configured in the task service.
+    /// The configured default is used when omitted
+    #[schema(value_type = Option<u64>)]
+    ttl: Option<Text<u64>>,
+    /// List of files to be included in the archive. A file name containing a relative path,
+    /// as sent by browsers for folder uploads, places the file in that directory of the archive
     #[schema(value_type = Vec<String>, format = Binary)]
     files: Vec<TempFile>,
+    /// Relative paths of the files in the archive, e.g. `photos/2024/a.jpg`, in the order of `files`.
+    /// Either omitted or given for every file, file names are used when omitted
+    #[schema(value_type = Option<Vec<String>>)]
+    path: Vec<Text<String>>,
 }
 
 #[derive(ToSchema)]
@@ -27,10 +83,221 @@ pub struct TaskIdResponse {
     task_id: String,
 }
 
+/// Maps the format names accepted by the form to the proto enum.
+fn parse_format(format: &str) -> Option<ArchiveFormat> {
+    match format {
+        "zip" => Some(ArchiveFormat::Zip),
+        "tar" => Some(ArchiveFormat::Tar),
+        "tar.gz" => Some(ArchiveFormat::TarGz),
+        "tar.zst" => Some(ArchiveFormat::TarZst),
+        "tar.xz" => Some(ArchiveFormat::TarXz),
+        "7z" => Some(ArchiveFormat::SevenZ),
+        _ => None,
+    }
+}
+
+/// Maps the encryption names accepted by the form to the proto enum.
+fn parse_encryption(encryption: &str) -> Option<Encryption> {
+    match encryption {
+        "none" => Some(Encryption::None),
+        "aes-128" => Some(Encryption::Aes128),
+        "aes-192" => Some(Encryption::Aes192),
+        "aes-256" => Some(Encryption::Aes256),
+        "zipcrypto" => Some(Encryption::ZipCrypto),
+        _ => None,
+    }
+}
+
+/// Maps the duplicate policy names accepted by the form to the proto enum.
+fn parse_duplicate_policy(policy: &str) -> Option<DuplicatePolicy> {
+    match policy {
+        "rename" => Some(DuplicatePolicy::Rename),
+        "error" => Some(DuplicatePolicy::Error),
+        "overwrite-last" => Some(DuplicatePolicy::OverwriteLast),
+        "keep-first" => Some(DuplicatePolicy::KeepFirst),
+        _ => None,
+    }
+}
+
+/// Compression of a single file given in the `compression_overrides` form field.
+#[derive(Deserialize)]
+struct CompressionOverride {
+    method: String,
+    level: Option<i32>,
+}
+
+/// Modification time and permissions of a single file given in the `metadata` form field.
+#[derive(Deserialize)]
+struct FileMetadata {
+    modified_time: Option<i64>,
+    mode: Option<String>,
+}
+
+/// Maps the compression method names accepted by the form to the proto enum.
+fn parse_compression_method(method: &str) -> Option<CompressionMethod> {
+    match method {
+        "stored" => Some(CompressionMethod::Stored),
+        "deflated" => Some(CompressionMethod::Deflated),
+        "bzip2" => Some(CompressionMethod::Bzip2),
+        "zstd" => Some(CompressionMethod::Zstd),
+        _ => None,
+    }
+}
+
+fn parse_compression(method: &str, level: Option<i32>) -> Result<Compression, String> {
+    let method = parse_compression_method(method).ok_or_else(|| format!("Unknown compression method {}", method))?;
+    Ok(Compression { method: method.into(), level })
+}
+
+/// Builds the upload header and the per-file settings from the form, the settings are keyed by path
+/// and have no file name or content yet.
+fn parse_form(form: &ArchiveForm) -> Result<(EnqueueTaskRequest, HashMap<String, FileInfo>), String> {
+    let format = match &form.format {
+        Some(name) => parse_format(name).ok_or_else(|| format!("Unknown archive format {}", name.as_str()))?,
+        None => ArchiveFormat::Zip,
+    };
+    let encryption = match &form.encryption {
+        Some(name) => parse_encryption(name).ok_or_else(|| format!("Unknown encryption {}", name.as_str()))?,
+        None => Encryption::Default,
+    };
+    let duplicates = match &form.duplicates {
+        Some(name) => parse_duplicate_policy(name).ok_or_else(|| format!("Unknown duplicate policy {}", name.as_str()))?,
+        None => DuplicatePolicy::Rename,
+    };
+    let level = form.compression_level.as_ref().map(|level| level.0);
+    let compression = match &form.compression_method {
+        Some(method) => Some(parse_compression(method, level)?),
+        None if level.is_some() => Some(Compression {
+            method: CompressionMethod::Deflated.into(),
+            level,
+        }),
+        None => None,
+    };
+
+    if !form.path.is_empty() && form.path.len() != form.files.len() {
+        return Err(format!("Got {} paths for {} files", form.path.len(), form.files.len()));
+    }
+
+    let mut file_settings: HashMap<String, FileInfo> = HashMap::new();
+    if let Some(json) = &form.compression_overrides {
+        let parsed: HashMap<String, CompressionOverride> = serde_json::from_str(json).map_err(|e| format!("Invalid compression overrides: {}", e))?;
+        for (filename, entry) in parsed {
+            file_settings.entry(filename).or_default().compression = Some(parse_compression(&entry.method, entry.level)?);
+        }
+    }
+    if let Some(json) = &form.metadata {
+        let parsed: HashMap<String, FileMetadata> = serde_json::from_str(json).map_err(|e| format!("Invalid metadata: {}", e))?;
+        for (filename, entry) in parsed {
+            let mode = match entry.mode {
+                Some(mode) => Some(u32::from_str_radix(&mode, 8).map_err(|_| format!("Invalid mode {} of {}", mode, filename))?),
+                None => None,
+            };
+            let settings = file_settings.entry(filename).or_default();
+            settings.modified_time = entry.modified_time;
+            settings.mode = mode;
+        }
+    }
+
+    let header = EnqueueTaskRequest {
+        archive_name: form.archive_name.clone(),
+        files: Vec::new(),
+        format: format.into(),
+        compression,
+        encryption: encryption.into(),
+        password: form.password.as_ref().map(|password| password.0.clone()),
+        duplicate_policy: duplicates.into(),
+        reproducible: form.reproducible.as_ref().is_some_and(|reproducible| reproducible.0),
+        source_date_epoch: form.source_date_epoch.as_ref().map(|epoch| epoch.0),
+        ttl: form.ttl.as_ref().map(|ttl| ttl.0),
+    };
+    Ok((header, file_settings))
+}
+
+/// Streams the header and the uploaded files to the task service.
+///
+/// Files are named by their `paths` if given, by their upload file names otherwise. If a file cannot be
+/// read, the upload is aborted so that the task service discards it, and the name of the file is returned.
+/// A closed channel means the RPC has already finished, so its result is left to the caller.
+async fn send_files(
+    header: EnqueueTaskRequest,
+    files: &[TempFile],
+    paths: &[Text<String>],
+    file_settings: &HashMap<String, FileInfo>,
+    tx: mpsc::Sender<EnqueueTaskChunk>,
+) -> Result<(), String> {
+    let chunk = |payload| EnqueueTaskChunk { payload: Some(payload) };
+    if tx.send(chunk(Payload::Header(header))).await.is_err() {
+        return Ok(());
+    }
+
+    let mut buffer = vec![0; UPLOAD_CHUNK_SIZE];
+    for (index, file) in files.iter().enumerate() {
+        let filename = match paths.get(index) {
+            Some(path) => path.0.clone(),
+            None => file.file_name.clone().unwrap_or_else(|| "unknown".to_string()),
+        };
+        let file_info = FileInfo {
+            filename: filename.clone(),
+            ..file_settings.get(&filename).cloned().unwrap_or_default()
+        };
+        if tx.send(chunk(Payload::File(file_info))).await.is_err() {
+            return Ok(());
+        }
+
+        match send_data(file.file.path(), &mut buffer, &tx).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => {
+                let _ = tx.send(chunk(Payload::Abort(format!("Failed to read file {}: {}", filename, e)))).await;
+                return Err(filename);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Streams the content of a file in data chunks, `false` if the channel was closed.
+async fn send_data(path: &Path, buffer: &mut [u8], tx: &mpsc::Sender<EnqueueTaskChunk>) -> io::Result<bool> {
+    let mut source = File::open(path).await?;
+    loop {
+        let read = source.read(buffer).await?;
+        if read == 0 {
+            return Ok(true);
+        }
+        let chunk = EnqueueTaskChunk {
+            payload: Some(Payload::Data(buffer[..read].to_vec())),
+        };
+        if tx.send(chunk).await.is_err() {
+            return Ok(false);
+        }
+    }
+}
+
+/// Streams the form to the task service, returns the ID of the new task or the error response.
+pub async fn enqueue(form: &ArchiveForm, data: &AppState) -> Result<ProtoTaskIdResponse, HttpResponse> {
+    let (header, file_settings) = parse_form(form).map_err(|message| HttpResponse::BadRequest().json(ErrorResponse::new("BadRequest", &message)))?;
+    let (tx, rx) = mpsc::channel(4);
+
+    let mut client = data.task_client.clone();
+    let request = client.enqueue_task_stream(ReceiverStream::new(rx)).map_err(error_response);
+    let upload = send_files(header, &form.files, &form.path, &file_settings, tx)
+        .map_err(|filename| HttpResponse::InternalServerError().json(ErrorResponse::new("InternalServerError", &format!("Failed to read file {}", filename))));
+
+    // A failed upload is aborted and rejected by the task service, wait for it so that no task is left behind
+    let (response, upload) = join(request, upload).await;
+    upload?;
+    Ok(response?.into_inner())
+}
+
 /// Enqueue an archive creation task.
 ///
 /// This endpoint enqueues a task to create an archive from the provided files.
-/// It accepts a multipart form containing the archive name and the files.
+/// It accepts a multipart form containing the archive name, the optional archive format, encryption and compression, and the files.
+/// Unless the encryption is none, the archive is encrypted with the given password or a generated one. Encrypted 7z archives also hide the file names.
+/// Zip archives encrypted with zipcrypto can be opened by legacy tools, but the encryption is weak.
+/// The files are streamed to the task service in chunks, so their total size is
+/// not limited by the gRPC message size.
 ///
 /// Returns a task ID which can be used to check the progress of the task.
 ///
@@ -45,28 +312,54 @@ pub struct TaskIdResponse {
     request_body(content = ArchiveForm, content_type = "multipart/form-data", description = "Form data containing the archive name and files"),
     responses(
         (status = 200, description = "Task enqueued successfully", body = TaskIdResponse),
+        (status = 400, description = "Unknown archive format, encryption, compression method or duplicate policy, or paths not matching the files", body = ErrorResponse),
+        (status = 429, description = "The task queue is full", body = ErrorResponse),
         (status = 500, description = "Failed to enqueue task", body = ErrorResponse)
     )
 )]
 #[post("/enqueue")]
 pub async fn enqueue_archive(MultipartForm(form): MultipartForm<ArchiveForm>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let archive_name = form.archive_name.clone();
-    let mut files = Vec::new();
-
-    for file in form.files.iter() {
-        let filename = file.file_name.clone().unwrap_or_else(|| "unknown".to_string());
-        let mut buffer = Vec::new();
-        if let Err(_e) = std::fs::File::open(&file.file).and_then(|mut f| f.read_to_end(&mut buffer)) {
-            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("InternalServerError", &format!("Failed to read file {}", filename))));
-        }
-        files.push(FileInfo { filename, content: buffer });
+    match enqueue(&form, &data).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(response) => Ok(response),
     }
+}
 
-    let request = Request::new(EnqueueTaskRequest { archive_name, files });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut client = data.task_client.lock().await;
-    match client.enqueue_task(request).await {
-        Ok(response) => Ok(HttpResponse::Ok().json(response.into_inner())),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(ErrorResponse::from(e))),
+    #[tokio::test]
+    async fn test_unreadable_file_aborts_the_upload() {
+        let readable = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(readable.path(), b"content").unwrap();
+        let missing = tempfile::NamedTempFile::new().unwrap();
+        std::fs::remove_file(missing.path()).unwrap();
+        let files: Vec<TempFile> = [readable, missing]
+            .into_iter()
+            .enumerate()
+            .map(|(index, file)| TempFile {
+                file,
+                content_type: None,
+                file_name: Some(format!("file{}.txt", index)),
+                size: 0,
+            })
+            .collect();
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let result = send_files(EnqueueTaskRequest::default(), &files, &[], &HashMap::new(), tx).await;
+        assert_eq!(result, Err("file1.txt".to_owned()));
+
+        let mut payloads = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            payloads.extend(chunk.payload);
+        }
+        assert!(matches!(&payloads[..], [
+            Payload::Header(_),
+            Payload::File(first),
+            Payload::Data(data),
+            Payload::File(second),
+            Payload::Abort(_),
+        ] if first.filename == "file0.txt" && data == b"content" && second.filename == "file1.txt"));
     }
 }
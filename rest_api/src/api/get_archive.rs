@@ -1,11 +1,27 @@
 use crate::{
-    api::task::{ArchiveResponse, GetArchiveRequest},
-    error::ErrorResponse,
+    api::task::{task_service_client::TaskServiceClient, ArchiveChunk, ReadArchiveRequest},
+    error::{error_response_with, ErrorResponse, GONE},
     AppState,
 };
-use actix_web::{get, web, Error, HttpResponse};
+use actix_web::{
+    body::SizedStream,
+    error::ErrorInternalServerError,
+    get,
+    http::{
+        header::{
+            ByteRangeSpec, ContentRange, ContentRangeSpec, ETag, EntityTag, Header, HttpDate, IfNoneMatch, IfRange, LastModified, Range, ACCEPT_RANGES,
+            IF_RANGE,
+        },
+        StatusCode,
+    },
+    web, Error, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Timelike};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use serde::Deserialize;
-use tonic::Request;
+use std::time::SystemTime;
+use tonic::{transport::Channel, Request, Status, Streaming};
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
@@ -14,10 +30,223 @@ pub struct GetArchiveQuery {
     taskId: String,
 }
 
+/// Most ranges served in one `multipart/byteranges` response, larger `Range` headers are ignored
+const MAX_RANGES: usize = 16;
+
+/// Sends the archive of a task, `410 Gone` once it has expired. `Range` requests get only the asked
+/// parts of the archive, `If-None-Match` and `If-Range` are checked against its ETag.
+pub async fn archive(task_id: String, req: &HttpRequest, data: &AppState) -> HttpResponse {
+    let mut client = data.task_client.clone();
+    // Reading nothing only returns the first chunk, which carries the archive name, size and ETag
+    let info = match read(&mut client, &task_id, 0, Some(0), None).await {
+        Ok(mut chunks) => match chunks.message().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return HttpResponse::InternalServerError().json(ErrorResponse::new("InternalServerError", "Archive stream is empty")),
+            Err(e) => return error_response_with(e, GONE),
+        },
+        Err(e) => return error_response_with(e, GONE),
+    };
+    // Later reads fail if the archive is no longer the one described here
+    let version = info.etag.clone();
+    let etag = EntityTag::new_strong(info.etag);
+    // HTTP dates have a precision of seconds, `If-Range` dates could not match otherwise
+    let last_modified = DateTime::parse_from_rfc3339(&info.modified_at)
+        .ok()
+        .and_then(|modified_at| modified_at.with_nanosecond(0))
+        .map(|modified_at| HttpDate::from(SystemTime::from(modified_at)));
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((ACCEPT_RANGES, "bytes"))
+        .insert_header(ETag(etag.clone()))
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", info.archive_name, info.extension),
+        ));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+
+    let not_modified = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        Err(_) => false,
+    };
+    if not_modified {
+        return response.status(StatusCode::NOT_MODIFIED).finish();
+    }
+
+    let size = info.size;
+    let specs = match Range::parse(req) {
+        Ok(Range::Bytes(specs)) if is_current(req, &etag, last_modified) => specs,
+        // Malformed and unknown ranges are ignored, and so are ranges of an archive that changed
+        _ => Vec::new(),
+    };
+    let ranges = match resolve_ranges(&specs, size) {
+        Some(ranges) => ranges,
+        None => {
+            return response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                }))
+                .json(ErrorResponse::new("RangeNotSatisfiable", &format!("The archive is {} bytes long", size)))
+        }
+    };
+
+    match ranges[..] {
+        [] => match read(&mut client, &task_id, 0, None, Some(&version)).await {
+            Ok(chunks) => response.content_type(info.content_type).body(SizedStream::new(size, bytes(chunks))),
+            Err(e) => error_response_with(e, GONE),
+        },
+        [(start, end)] => match read(&mut client, &task_id, start, Some(end - start + 1), Some(&version)).await {
+            Ok(chunks) => response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .content_type(info.content_type)
+                .insert_header(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((start, end)),
+                    instance_length: Some(size),
+                }))
+                .body(SizedStream::new(end - start + 1, bytes(chunks))),
+            Err(e) => error_response_with(e, GONE),
+        },
+        _ => {
+            let boundary = Uuid::new_v4().simple().to_string();
+            let Multipart { parts, tail, length } = multipart(&ranges, size, &info.content_type, &boundary);
+            let body = stream::iter(parts)
+                .then(move |Part { head, start, end }| {
+                    let mut client = client.clone();
+                    let task_id = task_id.clone();
+                    let version = version.clone();
+                    async move {
+                        let chunks = read(&mut client, &task_id, start, Some(end - start + 1), Some(&version))
+                            .await
+                            .map_err(ErrorInternalServerError)?;
+                        Ok::<_, Error>(
+                            stream::once(async move { Ok(web::Bytes::from(head)) })
+                                .chain(bytes(chunks))
+                                .chain(stream::once(async { Ok(web::Bytes::from_static(PART_END.as_bytes())) })),
+                        )
+                    }
+                })
+                .try_flatten()
+                .chain(stream::once(async move { Ok(web::Bytes::from(tail)) }));
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .content_type(format!("multipart/byteranges; boundary={}", boundary))
+                .body(SizedStream::new(length, body))
+        }
+    }
+}
+
+/// Resolves the ranges of a `Range` header against an archive of `size` bytes, `None` if none of
+/// them is satisfiable. The ranges are sorted and overlapping or adjacent ones merged, so that no
+/// byte is sent twice. An empty list means the whole archive, also when the ranges cover all of it.
+fn resolve_ranges(specs: &[ByteRangeSpec], size: u64) -> Option<Vec<(u64, u64)>> {
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Some(Vec::new());
+    }
+    let mut satisfiable: Vec<(u64, u64)> = specs.iter().filter_map(|spec| spec.to_satisfiable_range(size)).collect();
+    if satisfiable.is_empty() {
+        return None;
+    }
+    satisfiable.sort_unstable();
+    let mut ranges: Vec<(u64, u64)> = Vec::with_capacity(satisfiable.len());
+    for (start, end) in satisfiable {
+        match ranges.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => *last_end = (*last_end).max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    if ranges.iter().map(|(start, end)| end - start + 1).sum::<u64>() >= size {
+        return Some(Vec::new());
+    }
+    Some(ranges)
+}
+
+/// Line break that ends the data of every part of a `multipart/byteranges` body
+const PART_END: &str = "\r\n";
+
+/// One range of a `multipart/byteranges` body, sent after its head and followed by [`PART_END`]
+#[derive(Debug)]
+struct Part {
+    head: String,
+    start: u64,
+    end: u64,
+}
+
+/// Framing of a `multipart/byteranges` body, with its length in bytes
+#[derive(Debug)]
+struct Multipart {
+    parts: Vec<Part>,
+    /// Closing delimiter after the last part
+    tail: String,
+    length: u64,
+}
+
+fn multipart(ranges: &[(u64, u64)], size: u64, content_type: &str, boundary: &str) -> Multipart {
+    let parts: Vec<Part> = ranges
+        .iter()
+        .map(|&(start, end)| Part {
+            head: format!(
+                "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, start, end, size
+            ),
+            start,
+            end,
+        })
+        .collect();
+    let tail = format!("--{}--\r\n", boundary);
+    let length = parts
+        .iter()
+        .map(|part| (part.head.len() + PART_END.len()) as u64 + part.end - part.start + 1)
+        .sum::<u64>()
+        + tail.len() as u64;
+    Multipart { parts, tail, length }
+}
+
+async fn read(
+    client: &mut TaskServiceClient<Channel>,
+    task_id: &str,
+    offset: u64,
+    length: Option<u64>,
+    etag: Option<&str>,
+) -> Result<Streaming<ArchiveChunk>, Status> {
+    let request = Request::new(ReadArchiveRequest {
+        task_id: task_id.to_owned(),
+        offset,
+        length,
+        etag: etag.map(str::to_owned),
+    });
+    Ok(client.read_archive(request).await?.into_inner())
+}
+
+fn bytes(chunks: Streaming<ArchiveChunk>) -> impl Stream<Item = Result<web::Bytes, Error>> {
+    chunks.map(|chunk| chunk.map(|c| web::Bytes::from(c.data)).map_err(ErrorInternalServerError))
+}
+
+/// Whether the `If-Range` header, if any, names the current version of the archive. Dates only
+/// match the exact modification time, entity tags must match strongly.
+fn is_current(req: &HttpRequest, etag: &EntityTag, last_modified: Option<HttpDate>) -> bool {
+    if !req.headers().contains_key(IF_RANGE) {
+        return true;
+    }
+    match IfRange::parse(req) {
+        Ok(IfRange::EntityTag(tag)) => tag.strong_eq(etag),
+        Ok(IfRange::Date(date)) => last_modified == Some(date),
+        Err(_) => false,
+    }
+}
+
 /// Retrieve the created archive.
 ///
 /// This endpoint retrieves the archive for the specified task ID. If the archive is found,
-/// it is returned as a binary response with `application/zip` content type.
+/// it is streamed as a binary response with the content type and file extension of its format,
+/// e.g. `application/zip` and `.zip` or `application/gzip` and `.tar.gz`. Archives are deleted
+/// once they expire, see `expires_at` in the progress, after which `410 Gone` is returned.
+///
+/// `Range` requests get only the requested parts of the archive, so that interrupted downloads can
+/// be resumed. `If-Range` and `If-None-Match` are checked against the `ETag` of the archive.
 ///
 /// Example of a successful response:
 /// ```zip
@@ -29,22 +258,108 @@ pub struct GetArchiveQuery {
         ("taskId" = String, description = "Task ID for which the archive is to be retrieved")
     ),
     responses(
-        (status = 200, description = "Archive retrieved successfully", content_type = "application/zip"),
-        (status = 404, description = "Archive not found", body = ErrorResponse)
+        (status = 200, description = "Archive retrieved successfully", content_type = ["application/zip", "application/x-tar", "application/gzip", "application/zstd", "application/x-xz", "application/x-7z-compressed"]),
+        (status = 206, description = "The requested ranges of the archive, several of them in a `multipart/byteranges` body"),
+        (status = 304, description = "The archive still has the ETag given in `If-None-Match`"),
+        (status = 404, description = "Archive not found", body = ErrorResponse),
+        (status = 410, description = "Archive expired and was deleted", body = ErrorResponse),
+        (status = 416, description = "None of the requested ranges is within the archive", body = ErrorResponse)
     )
 )]
 #[get("/archive")]
-pub async fn get_archive(query: web::Query<GetArchiveQuery>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let request = Request::new(GetArchiveRequest { task_id: query.taskId.clone() });
-    let mut client = data.task_client.lock().await;
-    match client.get_archive(request).await {
-        Ok(response) => {
-            let archive_response: ArchiveResponse = response.into_inner();
-            Ok(HttpResponse::Ok()
-                .content_type("application/x-zip-compressed")
-                .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\".zip", archive_response.archive_name)))
-                .body(archive_response.archive))
+pub async fn get_archive(query: web::Query<GetArchiveQuery>, req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    Ok(archive(query.taskId.clone(), &req, &data).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn specs(header: &str) -> Vec<ByteRangeSpec> {
+        match header.parse::<Range>().unwrap() {
+            Range::Bytes(specs) => specs,
+            Range::Unregistered(..) => panic!("not a bytes range"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_ranges_merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(resolve_ranges(&specs("bytes=50-59,0-9,5-19,20-29"), 100), Some(vec![(0, 29), (50, 59)]));
+        assert_eq!(resolve_ranges(&specs("bytes=10-19,12-15"), 100), Some(vec![(10, 19)]));
+        assert_eq!(resolve_ranges(&specs("bytes=10-19,21-29"), 100), Some(vec![(10, 19), (21, 29)]));
+    }
+
+    #[test]
+    fn test_resolve_ranges_covering_the_archive() {
+        let repeated = vec!["0-"; MAX_RANGES].join(",");
+        assert_eq!(resolve_ranges(&specs(&format!("bytes={}", repeated)), 100), Some(Vec::new()));
+        assert_eq!(resolve_ranges(&specs("bytes=0-49,50-"), 100), Some(Vec::new()));
+        assert_eq!(resolve_ranges(&specs("bytes=0-49,40-99"), 100), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_resolve_ranges_suffix_ranges() {
+        assert_eq!(resolve_ranges(&specs("bytes=-10"), 100), Some(vec![(90, 99)]));
+        assert_eq!(resolve_ranges(&specs("bytes=-10,85-94"), 100), Some(vec![(85, 99)]));
+        // A suffix longer than the archive is all of it
+        assert_eq!(resolve_ranges(&specs("bytes=-200"), 100), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_resolve_ranges_unsatisfiable_and_ignored() {
+        assert_eq!(resolve_ranges(&specs("bytes=100-,200-299"), 100), None);
+        assert_eq!(resolve_ranges(&specs("bytes=100-,0-9"), 100), Some(vec![(0, 9)]));
+        assert_eq!(resolve_ranges(&[], 100), Some(Vec::new()));
+        let too_many = (0..=MAX_RANGES).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>().join(",");
+        assert_eq!(resolve_ranges(&specs(&format!("bytes={}", too_many)), 100), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_resolve_ranges_empty_archive() {
+        assert_eq!(resolve_ranges(&specs("bytes=0-"), 0), None);
+        assert_eq!(resolve_ranges(&specs("bytes=-10"), 0), None);
+        assert_eq!(resolve_ranges(&[], 0), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_is_current() {
+        let etag = EntityTag::new_strong("abc".to_owned());
+        let modified = HttpDate::from(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000));
+        let earlier = HttpDate::from(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000));
+        let current = |if_range: Option<String>| {
+            let mut req = TestRequest::default();
+            if let Some(if_range) = if_range {
+                req = req.insert_header((IF_RANGE, if_range));
+            }
+            is_current(&req.to_http_request(), &etag, Some(modified))
+        };
+        assert!(current(None));
+        assert!(current(Some("\"abc\"".to_owned())));
+        assert!(!current(Some("\"other\"".to_owned())));
+        assert!(!current(Some("W/\"abc\"".to_owned())));
+        assert!(current(Some(modified.to_string())));
+        assert!(!current(Some(earlier.to_string())));
+        assert!(!current(Some("garbage".to_owned())));
+    }
+
+    #[test]
+    fn test_multipart_length() {
+        let data: Vec<u8> = (0..=255).collect();
+        let ranges = [(0, 0), (10, 19), (200, 255)];
+        let Multipart { parts, tail, length } = multipart(&ranges, data.len() as u64, "application/zip", "boundary");
+        let mut body = Vec::new();
+        for part in &parts {
+            body.extend_from_slice(part.head.as_bytes());
+            body.extend_from_slice(&data[part.start as usize..=part.end as usize]);
+            body.extend_from_slice(PART_END.as_bytes());
         }
-        Err(e) => Ok(HttpResponse::InternalServerError().json(ErrorResponse::from(e))),
+        body.extend_from_slice(tail.as_bytes());
+        assert_eq!(length, body.len() as u64);
+        assert_eq!(
+            parts[1].head,
+            "--boundary\r\nContent-Type: application/zip\r\nContent-Range: bytes 10-19/256\r\n\r\n"
+        );
+        assert_eq!(tail, "--boundary--\r\n");
     }
 }
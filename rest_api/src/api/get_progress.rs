@@ -1,14 +1,19 @@
 use super::task::task_service_client::TaskServiceClient;
 use crate::{
-    api::task::{AllTasksRequest, AllTasksResponse as ProtoAllTasksResponse, TaskProgressRequest, TaskProgressResponse as ProtoTaskProgressResponse},
-    error::ErrorResponse,
+    api::task::{
+        AllTasksRequest, AllTasksResponse as ProtoAllTasksResponse, SortOrder, TaskProgressRequest, TaskProgressResponse as ProtoTaskProgressResponse,
+    },
+    error::{error_response, ErrorResponse},
     AppState,
 };
-use actix_web::{get, web, Error, HttpResponse};
+use actix_web::{get, web, Error, HttpRequest, HttpResponse};
 use serde::Deserialize;
 use tonic::{transport::Channel, Request, Status};
 use utoipa::ToSchema;
 
+/// Largest page of tasks the task service returns
+const MAX_PAGE_SIZE: u32 = 1000;
+
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 pub struct GetProgressQuery {
@@ -16,6 +21,44 @@ pub struct GetProgressQuery {
     taskId: Option<String>,
 }
 
+/// Filters, order and page of a task listing.
+#[derive(Deserialize)]
+pub struct ListTasksQuery {
+    page_size: Option<u32>,
+    page_token: Option<String>,
+    /// Comma-separated states
+    state: Option<String>,
+    archive_name_prefix: Option<String>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+    /// `oldest-first` or `newest-first`
+    sort: Option<String>,
+}
+
+impl ListTasksQuery {
+    /// Whether a page of the tasks is asked for rather than all of them
+    pub fn is_paged(&self) -> bool {
+        self.page_size.is_some() || self.page_token.is_some()
+    }
+
+    pub fn to_request(&self) -> Result<AllTasksRequest, String> {
+        let sort_order = match self.sort.as_deref() {
+            None | Some("oldest-first") => SortOrder::OldestFirst,
+            Some("newest-first") => SortOrder::NewestFirst,
+            Some(sort) => return Err(format!("Unknown sort order {}", sort)),
+        };
+        Ok(AllTasksRequest {
+            page_size: self.page_size.unwrap_or_default(),
+            page_token: self.page_token.clone().unwrap_or_default(),
+            states: self.state.iter().flat_map(|states| states.split(',')).map(str::to_owned).collect(),
+            archive_name_prefix: self.archive_name_prefix.clone().unwrap_or_default(),
+            created_after: self.created_after.clone().unwrap_or_default(),
+            created_before: self.created_before.clone().unwrap_or_default(),
+            sort_order: sort_order.into(),
+        })
+    }
+}
+
 #[derive(ToSchema)]
 #[allow(unused)]
 #[schema(description = "Response containing the progress details of a task")]
@@ -28,6 +71,51 @@ pub struct SingleTaskResponse {
     progress: i16,
     /// An error message if the task failed
     error: Option<String>,
+    /// The lifecycle state of the task: queued, running, completed, failed, cancelled or expired
+    state: String,
+    /// The states the task went through with the time each was entered
+    transitions: Vec<StateTransitionResponse>,
+    /// The one-based position of the task in the queue while it is queued, 0 otherwise
+    queue_position: u32,
+    /// The number of input bytes compressed so far
+    bytes_processed: u64,
+    /// The total number of input bytes of the archive
+    bytes_total: u64,
+    /// The name of the file being compressed
+    current_file: String,
+    /// The number of bytes of the current file compressed so far
+    current_file_bytes_processed: u64,
+    /// The size of the current file in bytes
+    current_file_bytes_total: u64,
+    /// Whether the password of the archive can still be revealed
+    password_available: bool,
+    /// Uploaded files that were renamed or left out because another file had the same path
+    duplicates: Vec<DuplicateFileResponse>,
+    /// The RFC 3339 time at which the archive of a completed task will be deleted, or was deleted if the task expired.
+    /// Empty until the task is completed
+    expires_at: String,
+}
+
+#[derive(ToSchema)]
+#[allow(unused)]
+#[schema(description = "An uploaded file that was renamed or left out by the duplicate policy")]
+pub struct DuplicateFileResponse {
+    /// The zero-based position of the file in the upload
+    index: u32,
+    /// The path the file was uploaded with
+    filename: String,
+    /// The path of the file in the archive, null if it was left out
+    archived_as: Option<String>,
+}
+
+#[derive(ToSchema)]
+#[allow(unused)]
+#[schema(description = "A state of a task and the time it was entered")]
+pub struct StateTransitionResponse {
+    /// The state the task moved to
+    state: String,
+    /// The time of the transition in RFC 3339 format
+    timestamp: String,
 }
 
 #[derive(ToSchema)]
@@ -36,6 +124,10 @@ pub struct SingleTaskResponse {
 pub struct AllTasksResponse {
     /// List of tasks with their progress details
     tasks: Vec<SingleTaskResponse>,
+    /// The `page_token` of the next page, empty on the last page
+    next_page_token: String,
+    /// The number of tasks matching the filters on all pages
+    total_size: u32,
 }
 
 #[derive(ToSchema)]
@@ -45,24 +137,41 @@ pub enum TaskProgressResponse {
     All(AllTasksResponse)
 }
 
-async fn fetch_task_progress(task_id: String, client: &mut TaskServiceClient<Channel>) -> Result<ProtoTaskProgressResponse, Status> {
+pub async fn fetch_task_progress(task_id: String, client: &mut TaskServiceClient<Channel>) -> Result<ProtoTaskProgressResponse, Status> {
     let request = Request::new(TaskProgressRequest { task_id });
     let response = client.get_task_progress(request).await?;
     Ok(response.into_inner())
 }
 
-async fn fetch_all_tasks(client: &mut TaskServiceClient<Channel>) -> Result<ProtoAllTasksResponse, Status> {
-    let request = Request::new(AllTasksRequest {});
+pub async fn fetch_all_tasks(request: AllTasksRequest, client: &mut TaskServiceClient<Channel>) -> Result<ProtoAllTasksResponse, Status> {
+    let request = Request::new(request);
     let response = client.get_all_tasks(request).await?;
     Ok(response.into_inner())
 }
 
+/// Lists every task matching the filters of `request`, a page of the largest size at a time.
+pub async fn fetch_every_task(mut request: AllTasksRequest, client: &mut TaskServiceClient<Channel>) -> Result<ProtoAllTasksResponse, Status> {
+    request.page_size = MAX_PAGE_SIZE;
+    let mut all_tasks = fetch_all_tasks(request.clone(), client).await?;
+    while !all_tasks.next_page_token.is_empty() {
+        request.page_token = std::mem::take(&mut all_tasks.next_page_token);
+        let page = fetch_all_tasks(request.clone(), client).await?;
+        all_tasks.tasks.extend(page.tasks);
+        all_tasks.next_page_token = page.next_page_token;
+        all_tasks.total_size = page.total_size;
+    }
+    Ok(all_tasks)
+}
+
 /// Check the progress of a task or retrieve all tasks.
 ///
 /// This endpoint allows you to check the progress of a specific task by its ID,
 /// or to retrieve the list of all tasks and their progress. If a `taskId` is provided,
 /// it returns the progress of that specific task. If no `taskId` is provided,
-/// it returns the progress of all tasks.
+/// it returns the progress of all tasks, oldest first. The tasks can be filtered and sorted with
+/// the other parameters. With `page_size` or `page_token` only a page of the tasks is returned,
+/// 100 by default, and the `X-Next-Page-Token` header holds the `page_token` of the next page,
+/// it is missing on the last page. `X-Total-Count` tells how many tasks match the filters.
 ///
 /// Example of a successful response when `taskId` is provided:
 /// ```json
@@ -70,7 +179,22 @@ async fn fetch_all_tasks(client: &mut TaskServiceClient<Channel>) -> Result<Prot
 ///     "task_id": "123e4567-e89b-12d3-a456-426614174000",
 ///     "done": false,
 ///     "progress": 50,
-///     "error": null
+///     "error": null,
+///     "state": "running",
+///     "bytes_processed": 5242880,
+///     "bytes_total": 10485760,
+///     "current_file": "report.pdf",
+///     "current_file_bytes_processed": 1048576,
+///     "current_file_bytes_total": 2097152,
+///     "password_available": true,
+///     "duplicates": [
+///         { "index": 2, "filename": "report.pdf", "archived_as": "report (1).pdf" }
+///     ],
+///     "expires_at": "",
+///     "transitions": [
+///         { "state": "queued", "timestamp": "2024-07-01T10:00:00+00:00" },
+///         { "state": "running", "timestamp": "2024-07-01T10:00:01+00:00" }
+///     ]
 /// }
 /// ```
 ///
@@ -82,13 +206,15 @@ async fn fetch_all_tasks(client: &mut TaskServiceClient<Channel>) -> Result<Prot
 ///             "task_id": "123e4567-e89b-12d3-a456-426614174000",
 ///             "done": false,
 ///             "progress": 50,
-///             "error": null
+///             "error": null,
+///             "state": "running"
 ///         },
 ///         {
 ///             "task_id": "223e4567-e89b-12d3-a456-426614174001",
 ///             "done": true,
 ///             "progress": 100,
-///             "error": null
+///             "error": null,
+///             "state": "completed"
 ///         }
 ///     ]
 /// }
@@ -96,26 +222,55 @@ async fn fetch_all_tasks(client: &mut TaskServiceClient<Channel>) -> Result<Prot
 #[utoipa::path(
     path = "/api/v1/progress",
     params(
-        ("taskId" = Option<String>, description = "Optional Task ID to check the progress of a specific task")
+        ("taskId" = Option<String>, description = "Optional Task ID to check the progress of a specific task"),
+        ("page_size" = Option<u32>, Query, description = "Number of tasks per page, 100 by default and at most 1000. All tasks are returned without `page_size` and `page_token`"),
+        ("page_token" = Option<String>, Query, description = "Token of the page to list, from the previous page"),
+        ("state" = Option<String>, Query, description = "Comma-separated states of the tasks to list, e.g. `queued,running`"),
+        ("archive_name_prefix" = Option<String>, Query, description = "Prefix of the archive names of the tasks to list"),
+        ("created_after" = Option<String>, Query, description = "Only tasks created after this RFC 3339 time"),
+        ("created_before" = Option<String>, Query, description = "Only tasks created before this RFC 3339 time"),
+        ("sort" = Option<String>, Query, description = "`oldest-first` (default) or `newest-first`")
     ),
     responses(
         (status = 200, description = "Progress retrieved successfully", body = TaskProgressResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid filter or page token", body = ErrorResponse),
         (status = 404, description = "Task not found", body = ErrorResponse)
     )
 )]
 #[get("/progress")]
-pub async fn get_progress(query: web::Query<GetProgressQuery>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let mut client = data.task_client.lock().await;
+pub async fn get_progress(query: web::Query<GetProgressQuery>, req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let mut client = data.task_client.clone();
 
     if let Some(task_id) = &query.taskId {
         match fetch_task_progress(task_id.clone(), &mut client).await {
             Ok(progress_response) => Ok(HttpResponse::Ok().json(progress_response)),
-            Err(e) => Ok(HttpResponse::InternalServerError().json(ErrorResponse::from(e))),
+            Err(e) => Ok(error_response(e)),
         }
     } else {
-        match fetch_all_tasks(&mut client).await {
-            Ok(all_tasks_response) => Ok(HttpResponse::Ok().json(all_tasks_response.tasks)),
-            Err(e) => Ok(HttpResponse::InternalServerError().json(ErrorResponse::from(e))),
+        // The listing parameters are only read without `taskId`, so that they cannot break a lookup
+        let (paged, request) = match web::Query::<ListTasksQuery>::from_query(req.query_string())
+            .map_err(|e| e.to_string())
+            .and_then(|list| Ok((list.is_paged(), list.to_request()?)))
+        {
+            Ok(listing) => listing,
+            Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("BadRequest", &message))),
+        };
+        // Clients of v1 that do not ask for a page still get every task
+        let result = if paged {
+            fetch_all_tasks(request, &mut client).await
+        } else {
+            fetch_every_task(request, &mut client).await
+        };
+        match result {
+            Ok(all_tasks_response) => {
+                let mut response = HttpResponse::Ok();
+                response.insert_header(("X-Total-Count", all_tasks_response.total_size.to_string()));
+                if !all_tasks_response.next_page_token.is_empty() {
+                    response.insert_header(("X-Next-Page-Token", all_tasks_response.next_page_token));
+                }
+                Ok(response.json(all_tasks_response.tasks))
+            }
+            Err(e) => Ok(error_response(e)),
         }
     }
 }
@@ -1,8 +1,13 @@
 use actix_web::web;
+pub mod delete_task;
 pub mod enqueue;
 pub mod get_archive;
 pub mod get_progress;
+pub mod reveal_password;
 pub mod stop_task;
+pub mod v2;
+pub mod watch_progress;
+pub mod watch_tasks;
 
 pub mod task {
     tonic::include_proto!("task");
@@ -11,6 +16,10 @@ pub mod task {
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(enqueue::enqueue_archive)
         .service(get_archive::get_archive)
+        .service(watch_progress::watch_progress)
         .service(get_progress::get_progress)
-        .service(stop_task::stop_task);
+        .service(stop_task::stop_task)
+        .service(reveal_password::reveal_password)
+        .service(delete_task::delete_task)
+        .service(watch_tasks::watch_tasks);
 }
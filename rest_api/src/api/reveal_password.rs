@@ -0,0 +1,57 @@
+use crate::{
+    api::task::RevealPasswordRequest,
+    error::{error_response_with, GONE},
+    AppState,
+};
+use actix_web::{post, web, Error, HttpResponse};
+use tonic::Request;
+use utoipa::ToSchema;
+
+#[derive(ToSchema)]
+#[allow(unused)]
+#[schema(description = "Response containing the password of an archive")]
+pub struct RevealPasswordResponse {
+    /// The password of the archive
+    password: String,
+    /// How many more times the password can be revealed, it is wiped when this reaches 0
+    reveals_left: u32,
+}
+
+/// Responds with the password of an archive, `410 Gone` once it is wiped or if there is none.
+pub async fn reveal(task_id: String, data: &AppState) -> HttpResponse {
+    let mut client = data.task_client.clone();
+    match client.reveal_password(Request::new(RevealPasswordRequest { task_id })).await {
+        Ok(res) => HttpResponse::Ok().json(res.into_inner()),
+        Err(e) => error_response_with(e, GONE),
+    }
+}
+
+/// Reveal the password of an archive.
+///
+/// Passwords are not part of the task progress. This endpoint returns the password of an
+/// encrypted archive a limited number of times, once by default, after which it is wiped
+/// from the task service.
+///
+/// Example of a successful response:
+/// ```json
+/// {
+///     "password": "departure-goofy-paddling-unwoven-acorn",
+///     "reveals_left": 0
+/// }
+/// ```
+#[utoipa::path(
+    path = "/api/v1/tasks/{taskId}/password",
+    params(
+        ("taskId" = String, Path, description = "Task ID of the archive")
+    ),
+    responses(
+        (status = 200, description = "Password revealed", body = RevealPasswordResponse),
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 410, description = "The archive is not encrypted or its password was wiped", body = ErrorResponse),
+        (status = 500, description = "Failed to reveal the password", body = ErrorResponse)
+    )
+)]
+#[post("/tasks/{taskId}/password")]
+pub async fn reveal_password(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    Ok(reveal(path.into_inner(), &data).await)
+}
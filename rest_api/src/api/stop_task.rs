@@ -1,11 +1,11 @@
 use crate::{
     api::task::{StopTaskRequest, StopTaskResponse as ProtoStopTaskResponse},
-    error::ErrorResponse,
+    error::error_response,
     AppState,
 };
 use actix_web::{get, web, Error, HttpResponse};
 use serde::Deserialize;
-use tonic::Request;
+use tonic::{Request, Status};
 use utoipa::ToSchema;
 
 #[derive(Deserialize)]
@@ -23,6 +23,13 @@ pub struct StopTaskResponse {
     status: String,
 }
 
+/// Cancels a queued task or stops the creation of the archive of a running one.
+pub async fn stop(task_id: String, data: &AppState) -> Result<ProtoStopTaskResponse, Status> {
+    let mut client = data.task_client.clone();
+    let response = client.stop_task(Request::new(StopTaskRequest { task_id })).await?;
+    Ok(response.into_inner())
+}
+
 /// Stop a task.
 ///
 /// This endpoint stops a task by its ID. If the task is found and stopped successfully,
@@ -41,21 +48,14 @@ pub struct StopTaskResponse {
     ),
     responses(
         (status = 200, description = "Task stopped successfully", body = StopTaskResponse),
-        (status = 404, description = "Task not found", body = ErrorResponse)
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 409, description = "Task is already finished", body = ErrorResponse)
     )
 )]
 #[get("/stop")]
 pub async fn stop_task(query: web::Query<StopTaskQuery>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
-    let mut client = data.task_client.lock().await;
-    let request = Request::new(StopTaskRequest { task_id: query.taskId.clone() });
-    let response = client.stop_task(request).await;
-
-    match response {
-        Ok(res) => {
-            let proto_item = res.into_inner();
-            let item: ProtoStopTaskResponse = proto_item.into();
-            Ok(HttpResponse::Ok().json(item))
-        }
-        Err(e) => Ok(HttpResponse::InternalServerError().json(ErrorResponse::from(e))),
+    match stop(query.taskId.clone(), &data).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => Ok(error_response(e)),
     }
 }
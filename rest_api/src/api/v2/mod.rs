@@ -0,0 +1,13 @@
+use actix_web::web;
+pub mod tasks;
+
+/// Routes of the `/api/v2` scope, where tasks are resources addressed by their ID.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(tasks::create_task)
+        .service(tasks::list_tasks)
+        .service(tasks::get_task)
+        .service(tasks::remove_task)
+        .service(tasks::cancel_task)
+        .service(tasks::get_task_archive)
+        .service(tasks::reveal_task_password);
+}
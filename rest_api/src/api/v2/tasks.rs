@@ -0,0 +1,180 @@
+use crate::{
+    api::{
+        delete_task::delete,
+        enqueue::{enqueue, ArchiveForm},
+        get_archive::archive,
+        get_progress::{fetch_all_tasks, fetch_task_progress, ListTasksQuery},
+        reveal_password::reveal,
+        stop_task::stop,
+    },
+    error::{error_response, ErrorResponse},
+    AppState,
+};
+use actix_multipart::form::MultipartForm;
+use actix_web::{delete, get, http::header::LOCATION, post, web, Error, HttpRequest, HttpResponse};
+
+/// Create an archive.
+///
+/// Enqueues a task creating an archive from the files of the multipart form, which takes the
+/// same fields as `POST /api/v1/enqueue`. Responds with `202 Accepted`, the ID of the task and
+/// its location.
+#[utoipa::path(
+    path = "/api/v2/tasks",
+    request_body(content = ArchiveForm, content_type = "multipart/form-data", description = "Form data containing the archive name and files"),
+    responses(
+        (status = 202, description = "Task enqueued", body = TaskIdResponse, headers(("Location" = String, description = "URL of the task"))),
+        (status = 400, description = "Invalid form", body = ErrorResponse),
+        (status = 429, description = "The task queue is full", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue the task", body = ErrorResponse)
+    )
+)]
+#[post("/tasks")]
+pub async fn create_task(MultipartForm(form): MultipartForm<ArchiveForm>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    match enqueue(&form, &data).await {
+        Ok(response) => Ok(HttpResponse::Accepted()
+            .insert_header((LOCATION, format!("/api/v2/tasks/{}", response.task_id)))
+            .json(response)),
+        Err(response) => Ok(response),
+    }
+}
+
+/// List tasks.
+///
+/// Lists the tasks matching the filters a page at a time, 100 tasks by default, oldest first.
+/// The `next_page_token` of the response is passed as `page_token` to get the next page.
+#[utoipa::path(
+    path = "/api/v2/tasks",
+    params(
+        ("page_size" = Option<u32>, Query, description = "Number of tasks per page, 100 by default and at most 1000"),
+        ("page_token" = Option<String>, Query, description = "Token of the page to list, from the previous page"),
+        ("state" = Option<String>, Query, description = "Comma-separated states of the tasks to list, e.g. `queued,running`"),
+        ("archive_name_prefix" = Option<String>, Query, description = "Prefix of the archive names of the tasks to list"),
+        ("created_after" = Option<String>, Query, description = "Only tasks created after this RFC 3339 time"),
+        ("created_before" = Option<String>, Query, description = "Only tasks created before this RFC 3339 time"),
+        ("sort" = Option<String>, Query, description = "`oldest-first` (default) or `newest-first`")
+    ),
+    responses(
+        (status = 200, description = "A page of tasks and their progress", body = AllTasksResponse),
+        (status = 400, description = "Invalid filter or page token", body = ErrorResponse),
+        (status = 503, description = "The task service is unavailable", body = ErrorResponse)
+    )
+)]
+#[get("/tasks")]
+pub async fn list_tasks(query: web::Query<ListTasksQuery>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let request = match query.to_request() {
+        Ok(request) => request,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("BadRequest", &message))),
+    };
+    let mut client = data.task_client.clone();
+    match fetch_all_tasks(request, &mut client).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+/// Get the progress of a task.
+#[utoipa::path(
+    path = "/api/v2/tasks/{taskId}",
+    params(
+        ("taskId" = String, Path, description = "Task ID")
+    ),
+    responses(
+        (status = 200, description = "Progress of the task", body = SingleTaskResponse),
+        (status = 404, description = "Task not found", body = ErrorResponse)
+    )
+)]
+#[get("/tasks/{taskId}")]
+pub async fn get_task(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let mut client = data.task_client.clone();
+    match fetch_task_progress(path.into_inner(), &mut client).await {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+/// Delete a task and its archive.
+///
+/// A queued or running task is stopped first. If it does not stop in time, nothing is
+/// deleted and `409 Conflict` is returned, the request can then be repeated.
+#[utoipa::path(
+    path = "/api/v2/tasks/{taskId}",
+    params(
+        ("taskId" = String, Path, description = "Task ID")
+    ),
+    responses(
+        (status = 204, description = "Task and archive deleted"),
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 409, description = "The task is still stopping", body = ErrorResponse)
+    )
+)]
+#[delete("/tasks/{taskId}")]
+pub async fn remove_task(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    match delete(path.into_inner(), &data).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+/// Cancel a task.
+///
+/// A queued task is cancelled right away, a running task stops shortly after. Responds with
+/// `202 Accepted`, the progress tells once the task is cancelled.
+#[utoipa::path(
+    path = "/api/v2/tasks/{taskId}/cancel",
+    params(
+        ("taskId" = String, Path, description = "Task ID")
+    ),
+    responses(
+        (status = 202, description = "Task stopping", body = StopTaskResponse),
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 409, description = "Task is already finished", body = ErrorResponse)
+    )
+)]
+#[post("/tasks/{taskId}/cancel")]
+pub async fn cancel_task(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    match stop(path.into_inner(), &data).await {
+        Ok(response) => Ok(HttpResponse::Accepted().json(response)),
+        Err(e) => Ok(error_response(e)),
+    }
+}
+
+/// Download the archive of a task.
+///
+/// Supports `Range`, `If-Range` and `If-None-Match` like `GET /api/v1/archive`.
+#[utoipa::path(
+    path = "/api/v2/tasks/{taskId}/archive",
+    params(
+        ("taskId" = String, Path, description = "Task ID")
+    ),
+    responses(
+        (status = 200, description = "The archive", content_type = ["application/zip", "application/x-tar", "application/gzip", "application/zstd", "application/x-xz", "application/x-7z-compressed"]),
+        (status = 206, description = "The requested ranges of the archive, several of them in a `multipart/byteranges` body"),
+        (status = 304, description = "The archive still has the ETag given in `If-None-Match`"),
+        (status = 404, description = "Task not found or not completed", body = ErrorResponse),
+        (status = 410, description = "Archive expired and was deleted", body = ErrorResponse),
+        (status = 416, description = "None of the requested ranges is within the archive", body = ErrorResponse)
+    )
+)]
+#[get("/tasks/{taskId}/archive")]
+pub async fn get_task_archive(path: web::Path<String>, req: HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    Ok(archive(path.into_inner(), &req, &data).await)
+}
+
+/// Reveal the password of an archive.
+///
+/// Returns the password as often as the task service allows, once by default, and wipes it afterwards.
+#[utoipa::path(
+    path = "/api/v2/tasks/{taskId}/password",
+    params(
+        ("taskId" = String, Path, description = "Task ID")
+    ),
+    responses(
+        (status = 200, description = "Password revealed", body = RevealPasswordResponse),
+        (status = 404, description = "Task not found", body = ErrorResponse),
+        (status = 410, description = "The archive is not encrypted or its password was wiped", body = ErrorResponse)
+    )
+)]
+#[post("/tasks/{taskId}/password")]
+pub async fn reveal_task_password(path: web::Path<String>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    Ok(reveal(path.into_inner(), &data).await)
+}
@@ -0,0 +1,55 @@
+use crate::{api::task::TaskProgressRequest, error::error_response, AppState};
+use actix_web::{error::ErrorInternalServerError, get, web, Error, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use tonic::Request;
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct WatchProgressQuery {
+    /// Task ID of the task to watch
+    taskId: String,
+}
+
+/// Watch the progress of a task.
+///
+/// This endpoint streams the progress of the specified task as Server-Sent Events.
+/// A `progress` event is sent with the current state right away and then every time
+/// the progress, completion or error of the task changes. The stream ends once the
+/// task is done or has failed.
+///
+/// Example of an event:
+/// ```text
+/// event: progress
+/// data: {"task_id":"123e4567-e89b-12d3-a456-426614174000","done":false,"progress":50.0,"error":""}
+/// ```
+#[utoipa::path(
+    path = "/api/v1/progress/stream",
+    params(
+        ("taskId" = String, description = "Task ID of the task to watch")
+    ),
+    responses(
+        (status = 200, description = "Progress events of the task", content_type = "text/event-stream"),
+        (status = 404, description = "Task not found", body = ErrorResponse)
+    )
+)]
+#[get("/progress/stream")]
+pub async fn watch_progress(query: web::Query<WatchProgressQuery>, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let request = Request::new(TaskProgressRequest { task_id: query.taskId.clone() });
+    let mut client = data.task_client.clone();
+    let updates = match client.watch_task(request).await {
+        Ok(response) => response.into_inner(),
+        Err(e) => return Ok(error_response(e)),
+    };
+
+    let events = updates.map(|update| {
+        let progress = update.map_err(ErrorInternalServerError)?;
+        let json = serde_json::to_string(&progress)?;
+        Ok::<_, Error>(web::Bytes::from(format!("event: progress\ndata: {}\n\n", json)))
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(events))
+}
@@ -0,0 +1,167 @@
+use super::task::task_service_client::TaskServiceClient;
+use crate::{
+    api::task::{TaskProgressRequest, TaskProgressResponse, WatchTasksRequest},
+    AppState,
+};
+use actix_web::{get, web, Error, HttpRequest, HttpResponse};
+use actix_ws::{Closed, Message, MessageStream, Session};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tonic::{transport::Channel, Request, Status, Streaming};
+
+/// Messages sent by the client over the WebSocket
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { task_ids: Vec<String> },
+    Unsubscribe { task_ids: Vec<String> },
+}
+
+/// Messages sent to the client over the WebSocket
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ServerMessage {
+    Progress(Box<TaskProgressResponse>),
+    Error { task_id: Option<String>, message: String },
+}
+
+async fn send(session: &mut Session, message: &ServerMessage) -> Result<(), Closed> {
+    match serde_json::to_string(message) {
+        Ok(json) => session.text(json).await,
+        Err(_) => Ok(()),
+    }
+}
+
+/// Subscribes to the given tasks and sends their current progress right away.
+async fn subscribe(
+    task_ids: Vec<String>,
+    subscribed: &mut HashSet<String>,
+    session: &mut Session,
+    client: &mut TaskServiceClient<Channel>,
+) -> Result<(), Closed> {
+    for task_id in task_ids {
+        let message = match client.get_task_progress(Request::new(TaskProgressRequest { task_id: task_id.clone() })).await {
+            Ok(response) => {
+                subscribed.insert(task_id);
+                ServerMessage::Progress(Box::new(response.into_inner()))
+            }
+            Err(e) => ServerMessage::Error {
+                task_id: Some(task_id),
+                message: e.message().to_string(),
+            },
+        };
+        send(session, &message).await?;
+    }
+    Ok(())
+}
+
+/// Watches the changes of the given tasks. Nothing is watched without tasks, an empty request would watch all of them.
+async fn watch(task_ids: &HashSet<String>, client: &mut TaskServiceClient<Channel>) -> Result<Option<Streaming<TaskProgressResponse>>, Status> {
+    if task_ids.is_empty() {
+        return Ok(None);
+    }
+    let request = Request::new(WatchTasksRequest {
+        task_ids: task_ids.iter().cloned().collect(),
+    });
+    Ok(Some(client.watch_tasks(request).await?.into_inner()))
+}
+
+/// Next change of the watched tasks, never resolves while no task is watched.
+async fn next_update(updates: &mut Option<Streaming<TaskProgressResponse>>) -> Result<Option<TaskProgressResponse>, Status> {
+    match updates {
+        Some(updates) => updates.message().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn run_session(mut session: Session, mut messages: MessageStream, mut client: TaskServiceClient<Channel>) -> Result<(), Closed> {
+    let mut subscribed = HashSet::new();
+    let mut updates = None;
+
+    loop {
+        tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe { task_ids }) => {
+                        // Watch the tasks before their progress is sent, so that no change can be missed in between
+                        let mut watched = subscribed.clone();
+                        watched.extend(task_ids.iter().cloned());
+                        match watch(&watched, &mut client).await {
+                            Ok(watching) => {
+                                updates = watching;
+                                subscribe(task_ids, &mut subscribed, &mut session, &mut client).await?;
+                            }
+                            Err(e) => {
+                                let message = ServerMessage::Error { task_id: None, message: format!("Failed to watch tasks: {}", e.message()) };
+                                send(&mut session, &message).await?;
+                            }
+                        }
+                    }
+                    Ok(ClientMessage::Unsubscribe { task_ids }) => {
+                        for task_id in task_ids {
+                            subscribed.remove(&task_id);
+                        }
+                        // Updates of the remaining tasks keep coming from the current watch if it cannot be replaced
+                        if let Ok(watching) = watch(&subscribed, &mut client).await {
+                            updates = watching;
+                        }
+                    }
+                    Err(e) => {
+                        let message = ServerMessage::Error { task_id: None, message: format!("Invalid message: {}", e) };
+                        send(&mut session, &message).await?;
+                    }
+                },
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await?,
+                Some(Ok(Message::Close(reason))) => return session.close(reason).await,
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+            update = next_update(&mut updates) => match update {
+                Ok(Some(progress)) => {
+                    if subscribed.contains(&progress.task_id) {
+                        send(&mut session, &ServerMessage::Progress(Box::new(progress))).await?;
+                    }
+                }
+                Ok(None) | Err(_) => {
+                    let message = ServerMessage::Error { task_id: None, message: "Task updates are no longer available".into() };
+                    send(&mut session, &message).await?;
+                    break;
+                }
+            },
+        }
+    }
+
+    session.close(None).await
+}
+
+/// Watch the progress of multiple tasks over a WebSocket.
+///
+/// This endpoint upgrades the connection to a WebSocket on which the client can subscribe to
+/// and unsubscribe from any number of tasks. Progress events of all subscribed tasks are
+/// multiplexed over the connection as JSON messages, the current progress is sent on subscribe.
+///
+/// Example of client messages:
+/// ```json
+/// {"action": "subscribe", "task_ids": ["123e4567-e89b-12d3-a456-426614174000"]}
+/// {"action": "unsubscribe", "task_ids": ["123e4567-e89b-12d3-a456-426614174000"]}
+/// ```
+///
+/// Example of server messages:
+/// ```json
+/// {"event": "progress", "task_id": "123e4567-e89b-12d3-a456-426614174000", "done": false, "progress": 50.0, "error": ""}
+/// {"event": "error", "task_id": "223e4567-e89b-12d3-a456-426614174001", "message": "Task not found"}
+/// ```
+#[utoipa::path(
+    path = "/api/v1/ws",
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol")
+    )
+)]
+#[get("/ws")]
+pub async fn watch_tasks(req: HttpRequest, body: web::Payload, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(run_session(session, messages, data.task_client.clone()));
+
+    Ok(response)
+}
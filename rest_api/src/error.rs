@@ -1,4 +1,6 @@
+use actix_web::{http::StatusCode, HttpResponse};
 use serde::Serialize;
+use tonic::Code;
 use utoipa::ToSchema;
 
 #[derive(Serialize, ToSchema)]
@@ -27,3 +29,76 @@ impl From<tonic::Status> for ErrorResponse {
         }
     }
 }
+
+/// The HTTP status matching the code of a task service error.
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::InvalidArgument | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted | Code::FailedPrecondition => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Statuses of endpoints whose resources can be gone for good: the task service reports an expired
+/// archive or a wiped password as a failed precondition, which is `410 Gone` rather than a conflict.
+pub const GONE: &[(Code, StatusCode)] = &[(Code::FailedPrecondition, StatusCode::GONE)];
+
+/// Responds with a task service error and the HTTP status matching its code.
+pub fn error_response(status: tonic::Status) -> HttpResponse {
+    error_response_with(status, &[])
+}
+
+/// Like `error_response`, with the statuses of an endpoint that differ from `http_status` for some codes.
+pub fn error_response_with(status: tonic::Status, overrides: &[(Code, StatusCode)]) -> HttpResponse {
+    let http_status = overrides
+        .iter()
+        .find(|(code, _)| *code == status.code())
+        .map_or_else(|| http_status(status.code()), |&(_, http_status)| http_status);
+    HttpResponse::build(http_status).json(ErrorResponse::from(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_status() {
+        for (code, expected) in [
+            (Code::Ok, StatusCode::INTERNAL_SERVER_ERROR),
+            (Code::InvalidArgument, StatusCode::BAD_REQUEST),
+            (Code::OutOfRange, StatusCode::BAD_REQUEST),
+            (Code::Unauthenticated, StatusCode::UNAUTHORIZED),
+            (Code::PermissionDenied, StatusCode::FORBIDDEN),
+            (Code::NotFound, StatusCode::NOT_FOUND),
+            (Code::AlreadyExists, StatusCode::CONFLICT),
+            (Code::Aborted, StatusCode::CONFLICT),
+            (Code::FailedPrecondition, StatusCode::CONFLICT),
+            (Code::ResourceExhausted, StatusCode::TOO_MANY_REQUESTS),
+            (Code::Unimplemented, StatusCode::NOT_IMPLEMENTED),
+            (Code::Unavailable, StatusCode::SERVICE_UNAVAILABLE),
+            (Code::DeadlineExceeded, StatusCode::GATEWAY_TIMEOUT),
+            (Code::Internal, StatusCode::INTERNAL_SERVER_ERROR),
+            (Code::Unknown, StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            assert_eq!(http_status(code), expected, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn test_error_response_overrides() {
+        let status = || tonic::Status::failed_precondition("Archive expired");
+        assert_eq!(error_response(status()).status(), StatusCode::CONFLICT);
+        assert_eq!(error_response_with(status(), GONE).status(), StatusCode::GONE);
+        assert_eq!(
+            error_response_with(tonic::Status::not_found("Task not found"), GONE).status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -1,9 +1,10 @@
+use actix_multipart::form::MultipartFormConfig;
 use actix_web::{web, App, HttpServer};
 use api::task::task_service_client::TaskServiceClient;
+use common::parse_size;
 use config::{Config, Environment, File};
 use serde::Deserialize;
-use std::{error::Error, io, sync::Arc};
-use tokio::sync::Mutex;
+use std::{error::Error, io};
 use tonic::transport::Channel;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
@@ -21,11 +22,13 @@ struct TaskServiceConfig {
 struct RestApiConfig {
     address: String,
     protocol: String,
+    max_upload_size: String,
 }
 
 #[derive(Clone)]
 struct AppState {
-    task_client: Arc<Mutex<TaskServiceClient<Channel>>>,
+    /// Clones share the connection to the task service, handlers clone it instead of waiting for each other
+    task_client: TaskServiceClient<Channel>,
 }
 
 #[actix_web::main]
@@ -36,7 +39,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             title = "Archive Creator API",
             description = "API for creating password-protected archives from uploaded files. \
             This service supports asynchronous processing and provides endpoints for enqueuing tasks, \
-            checking progress, retrieving archives, and stopping tasks.",
+            checking progress, retrieving archives and their passwords, and stopping tasks.",
             version = "0.0.1",
             contact(
                 name = "API Support",
@@ -53,14 +56,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
             api::enqueue::enqueue_archive,
             api::get_archive::get_archive,
             api::get_progress::get_progress,
+            api::watch_progress::watch_progress,
+            api::watch_tasks::watch_tasks,
             api::stop_task::stop_task,
+            api::reveal_password::reveal_password,
+            api::delete_task::delete_task,
+            api::v2::tasks::create_task,
+            api::v2::tasks::list_tasks,
+            api::v2::tasks::get_task,
+            api::v2::tasks::remove_task,
+            api::v2::tasks::cancel_task,
+            api::v2::tasks::get_task_archive,
+            api::v2::tasks::reveal_task_password,
         ),
         components(schemas(
             api::enqueue::ArchiveForm,
             api::get_progress::TaskProgressResponse,
             api::get_progress::SingleTaskResponse,
+            api::get_progress::StateTransitionResponse,
+            api::get_progress::DuplicateFileResponse,
             api::get_progress::AllTasksResponse,
             api::stop_task::StopTaskResponse,
+            api::reveal_password::RevealPasswordResponse,
             api::enqueue::TaskIdResponse,
             error::ErrorResponse
         )),
@@ -83,22 +100,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let task_client = TaskServiceClient::connect(format!("{}://{}", &task_service_config.protocol, &task_service_config.address))
         .await
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    let app_state = AppState {
-        task_client: Arc::new(Mutex::new(task_client)),
-    };
+    // Parse max upload size
+    let max_upload_size = parse_size(&rest_api_config.max_upload_size)?;
+    let app_state = AppState { task_client };
     let openapi = ApiDoc::openapi();
 
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(app_state.clone()))
+            .app_data(MultipartFormConfig::default().total_limit(max_upload_size))
             .wrap(
                 actix_cors::Cors::default()
                     .allow_any_origin()
                     .allow_any_method()
                     .allow_any_header()
+                    .expose_headers(["Location", "X-Next-Page-Token", "X-Total-Count", "ETag", "Accept-Ranges", "Content-Range"])
                     .max_age(3600),
             )
             .service(web::scope("/api/v1").configure(api::init_routes))
+            .service(web::scope("/api/v2").configure(api::v2::init_routes))
             .service(SwaggerUi::new("/swagger/{_:.*}").url("/api/docs/openapi.json", openapi.clone()))
     })
     .bind(&rest_api_config.address)?;
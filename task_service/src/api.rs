@@ -1,176 +1,954 @@
-use crate::models::task::Task;
-use crate::services::task_service::create_zip_with_password;
-use crate::utils::zip::generate_random_password;
-use std::collections::HashMap;
+use crate::models::archive_format::ArchiveFormat;
+use crate::models::archive_options::ArchiveOptions;
+use crate::models::compression::{Compression, CompressionMethod};
+use crate::models::duplicate_policy::{DuplicateFile, DuplicatePolicy};
+use crate::models::encryption::Encryption;
+use crate::models::staged_file::StagedFile;
+use crate::models::task::{parse_timestamp, Task, TaskState};
+use crate::models::task_query::{TaskQuery, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::services::janitor::Janitor;
+use crate::services::job_queue::{Job, JobQueue};
+use crate::services::password_vault::PasswordVault;
+use crate::services::runner::TaskRunner;
+use crate::services::upload::UploadStage;
+use crate::settings::{PasswordConfig, RetentionConfig, TaskServiceConfig};
+use crate::store::TaskStore;
+use crate::utils::archive_path::archive_file_path;
+use crate::utils::password::{check_password, generate_password};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::Read;
-use std::sync::atomic::Ordering;
+use std::io::{self, Read, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use task::enqueue_task_chunk::Payload;
 use task::task_service_server::TaskService;
 use task::{
-    AllTasksRequest, AllTasksResponse, ArchiveResponse, EnqueueTaskRequest, GetArchiveRequest, StopTaskRequest, StopTaskResponse, TaskIdResponse,
-    TaskProgressRequest, TaskProgressResponse,
+    AllTasksRequest, AllTasksResponse, ArchiveChunk, ArchiveFormat as ProtoArchiveFormat, ArchiveResponse, Compression as ProtoCompression,
+    CompressionMethod as ProtoCompressionMethod, DeleteTaskRequest, DeleteTaskResponse, DuplicateFile as ProtoDuplicateFile,
+    DuplicatePolicy as ProtoDuplicatePolicy, Encryption as ProtoEncryption, EnqueueTaskChunk, EnqueueTaskRequest, GetArchiveRequest, ReadArchiveRequest,
+    RevealPasswordRequest, RevealPasswordResponse, SortOrder, StateTransition, StopTaskRequest, StopTaskResponse, TaskIdResponse, TaskProgressRequest,
+    TaskProgressResponse, WatchTasksRequest,
 };
-use tokio::sync::Mutex;
-use tokio::time::sleep;
-use tonic::{Request, Response, Status};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
 use uuid::Uuid;
 
 pub mod task {
     tonic::include_proto!("task");
 }
 
-#[derive(Default)]
+/// Size of the chunks sent by `StreamArchive` and `ReadArchive`
+const ARCHIVE_CHUNK_SIZE: usize = 64 * 1024;
+/// Time of the entries of reproducible archives without an epoch, 1980-01-01 is the earliest time zip can store
+const DEFAULT_SOURCE_DATE_EPOCH: i64 = 315_532_800;
+/// Number of progress updates buffered for slow task watchers
+const TASK_EVENTS_CAPACITY: usize = 1024;
+/// How long `DeleteTask` waits for a running task to stop
+const DELETE_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct TaskServiceImpl {
-    tasks: Arc<Mutex<HashMap<String, Task>>>,
+    runner: TaskRunner,
+    workers: usize,
     archive_path: String,
-    simulate_slow_work: bool,
-    slow_work_duration: u64,
+    passwords: PasswordConfig,
+    vault: PasswordVault,
+    retention: RetentionConfig,
 }
 
 impl TaskServiceImpl {
-    pub fn new(archive_path: &str, simulate_slow_work: bool, slow_work_duration: u64) -> Self {
+    pub fn new(store: Box<dyn TaskStore>, vault: PasswordVault, config: &TaskServiceConfig) -> Self {
+        let runner = TaskRunner::new(
+            Arc::new(Mutex::new(store)),
+            Arc::new(JobQueue::new(config.max_queue_length)),
+            broadcast::channel(TASK_EVENTS_CAPACITY).0,
+            config.simulate_slow_work,
+            config.slow_work_duration,
+        );
         Self {
-            tasks: Arc::new(Mutex::new(HashMap::new())),
-            archive_path: archive_path.to_owned(),
-            simulate_slow_work,
-            slow_work_duration,
+            runner,
+            workers: config.workers,
+            archive_path: config.archive_path.clone(),
+            passwords: config.passwords.clone(),
+            vault,
+            retention: config.retention.clone(),
         }
     }
 
-    pub fn get_file_path(&self, task_id: &str) -> String {
-        format!("{}/{}.zip", self.archive_path, task_id)
+    pub fn get_file_path(&self, task_id: &str, format: ArchiveFormat) -> String {
+        archive_file_path(&self.archive_path, task_id, format)
     }
-}
 
-#[tonic::async_trait]
-impl TaskService for TaskServiceImpl {
-    async fn enqueue_task(&self, request: Request<EnqueueTaskRequest>) -> Result<Response<TaskIdResponse>, Status> {
-        let req = request.into_inner();
-        let task_id = Uuid::new_v4().to_string();
-        let file_path = self.get_file_path(&task_id);
-        let password = generate_random_password();
-        let archive_name = req.archive_name;
-        let files = req.files;
-
-        let task = Task::new(&task_id, &archive_name, &password);
-        let stop_signal = task.stop_signal.clone();
-        let mut tasks = self.tasks.lock().await;
-        tasks.insert(task_id.clone(), task);
-
-        // Start the archive creation process in a new async task
-        let task_id_clone = task_id.clone();
-        let tasks_clone = self.tasks.clone();
-        let simulate_slow_work = self.simulate_slow_work;
-        let slow_work_duration = self.slow_work_duration;
+    pub fn get_upload_dir(&self, task_id: &str) -> String {
+        format!("{}/{}.upload", self.archive_path, task_id)
+    }
 
-        tokio::spawn(async move {
-            if simulate_slow_work {
-                let total_steps = 10;
-                let step_duration = Duration::from_millis(slow_work_duration / total_steps);
-                for step in 1..=total_steps {
-                    sleep(step_duration).await;
-                    if stop_signal.load(Ordering::Relaxed) {
-                        let mut tasks = tasks_clone.lock().await;
-                        tasks.remove(&task_id_clone);
-                        return;
-                    }
+    /// Recovers the tasks of a previous run, then starts the workers and the cleanup of archives,
+    /// so that neither sees a task before it is recovered.
+    pub async fn start(&self) -> io::Result<()> {
+        self.recover_tasks().await?;
+        self.runner.start(self.workers);
+        Janitor::new(self.runner.clone(), &self.archive_path, &self.retention).start();
+        Ok(())
+    }
+
+    /// Marks tasks that were interrupted by a restart as failed and removes their leftovers,
+    /// completed tasks are kept so that their archives can still be retrieved until they expire.
+    async fn recover_tasks(&self) -> io::Result<()> {
+        let mut tasks = self.runner.tasks.lock().await;
+        for mut task in tasks.list()? {
+            // Archives completed before expiry was introduced are kept for the default time
+            if task.state == TaskState::Completed && task.expires_at.is_none() {
+                let completed_at = task.entered(TaskState::Completed).and_then(parse_timestamp).unwrap_or_else(Utc::now);
+                task.expires_at = TimeDelta::try_seconds(self.retention.ttl as i64)
+                    .and_then(|ttl| completed_at.checked_add_signed(ttl))
+                    .map(|time| time.to_rfc3339());
+                tasks.put(&task)?;
+            }
+            if task.state.is_finished() {
+                continue;
+            }
+            if task.fail("Task interrupted by a service restart".into()).is_ok() {
+                tasks.put(&task)?;
+            }
+            let _ = tokio::fs::remove_dir_all(self.get_upload_dir(&task.taskId)).await;
+            let _ = tokio::fs::remove_file(self.get_file_path(&task.taskId, task.format)).await;
+        }
+        Ok(())
+    }
+
+    async fn find_task(&self, task_id: &str) -> Result<Task, Status> {
+        let tasks = self.runner.tasks.lock().await;
+        tasks.get(task_id).map_err(store_error)?.ok_or_else(|| Status::not_found("Task not found"))
+    }
 
-                    let progress = (step as f64 / total_steps as f64) * 100.0;
-                    let mut tasks = tasks_clone.lock().await;
+    /// Finds a task whose archive is about to be downloaded and records the download,
+    /// which keeps the archive from being evicted when disk space runs low.
+    async fn find_archive(&self, task_id: &str) -> Result<Task, Status> {
+        let mut tasks = self.runner.tasks.lock().await;
+        let mut task = tasks.get(task_id).map_err(store_error)?.ok_or_else(|| Status::not_found("Task not found"))?;
+        match task.state {
+            TaskState::Completed => {}
+            TaskState::Expired => {
+                return Err(Status::failed_precondition(format!(
+                    "Archive expired at {}",
+                    task.expires_at.as_deref().unwrap_or_default()
+                )))
+            }
+            state => return Err(Status::not_found(format!("Task is {}, it has no archive", state))),
+        }
+        task.last_accessed = Some(Utc::now().to_rfc3339());
+        tasks.put(&task).map_err(store_error)?;
+        Ok(task)
+    }
+
+    /// Streams `length` bytes of the archive of a task starting at `offset`, or everything after `offset` without a length.
+    /// Fails unless the archive has the entity tag `etag`, if given.
+    async fn read_archive_file(
+        &self,
+        task_id: &str,
+        offset: u64,
+        length: Option<u64>,
+        etag: Option<&str>,
+    ) -> Result<ReceiverStream<Result<ArchiveChunk, Status>>, Status> {
+        let task = self.find_archive(task_id).await?;
+        let file_path = self.get_file_path(task_id, task.format);
+        let mut file = tokio::fs::File::open(&file_path)
+            .await
+            .map_err(|e| Status::not_found(format!("File not found: {:?}", e)))?;
+        let metadata = file
+            .metadata()
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read file metadata: {:?}", e)))?;
+        let size = metadata.len();
+        let modified_at: DateTime<Utc> = metadata.modified().map(DateTime::from).unwrap_or_default();
+        let current_etag = archive_etag(size, modified_at);
+        if etag.is_some_and(|etag| etag != current_etag) {
+            return Err(Status::failed_precondition("Archive changed since it was first read"));
+        }
+        if offset > size {
+            return Err(Status::out_of_range(format!(
+                "Offset {} is past the end of the archive of {} bytes",
+                offset, size
+            )));
+        }
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| Status::internal(format!("Failed to read file: {:?}", e)))?;
+        let mut remaining = length.map_or(size - offset, |length| length.min(size - offset));
 
-                    if let Some(task) = tasks.get_mut(&task_id_clone) {
-                        task.progress = progress;
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut first = Some(ArchiveChunk {
+                data: Vec::new(),
+                archive_name: task.archive_name,
+                extension: task.format.extension().to_owned(),
+                content_type: task.format.content_type().to_owned(),
+                size,
+                etag: current_etag,
+                modified_at: modified_at.to_rfc3339(),
+            });
+            loop {
+                let mut buffer = vec![0; usize::try_from(remaining).map_or(ARCHIVE_CHUNK_SIZE, |remaining| remaining.min(ARCHIVE_CHUNK_SIZE))];
+                let chunk = match file.read(&mut buffer).await {
+                    // The first chunk is always sent, so that empty archives and reads still carry the metadata
+                    Ok(0) if first.is_none() => break,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        remaining -= read as u64;
+                        let mut chunk = first.take().unwrap_or_default();
+                        chunk.data = buffer;
+                        Ok(chunk)
                     }
+                    Err(e) => Err(Status::internal(format!("Failed to read file: {:?}", e))),
+                };
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
                 }
             }
+        });
 
-            match create_zip_with_password(&file_path, files, &password) {
-                Ok(_) => {
-                    let mut tasks = tasks_clone.lock().await;
-                    if let Some(task) = tasks.get_mut(&task_id_clone) {
-                        task.done = true;
-                        task.progress = 100.0;
-                    }
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Creates a task from the chunks of an `EnqueueTaskStream` upload. The task is only created once the
+    /// stream has ended, the staged files are removed if it fails or is aborted.
+    async fn enqueue_chunks<S>(&self, mut stream: S) -> Result<TaskIdResponse, Status>
+    where
+        S: Stream<Item = Result<EnqueueTaskChunk, Status>> + Unpin,
+    {
+        let header = match stream.next().await.transpose()? {
+            Some(EnqueueTaskChunk {
+                payload: Some(Payload::Header(header)),
+            }) => header,
+            _ => return Err(Status::invalid_argument("First upload chunk must be the header")),
+        };
+        let options = archive_options(&header, &self.passwords).map_err(Status::invalid_argument)?;
+        let ttl = archive_ttl(&header, &self.retention).map_err(Status::invalid_argument)?;
+        if self.runner.queue.is_full() {
+            return Err(queue_full());
+        }
+        let archive_name = header.archive_name.clone();
+        let task_id = Uuid::new_v4().to_string();
+
+        let mut stage = UploadStage::new(&self.get_upload_dir(&task_id)).await.map_err(stage_error)?;
+        if let Err(status) = stage_stream(header, &mut stream, &mut stage).await {
+            stage.discard().await;
+            return Err(status);
+        }
+        let files = stage.finish().await.map_err(stage_error)?;
+
+        self.spawn_task(&task_id, &archive_name, options, ttl, files).await?;
+
+        Ok(TaskIdResponse { task_id })
+    }
+
+    /// Cancels a queued task, or asks the worker creating the archive of a running task to stop.
+    async fn stop(&self, task_id: &str) {
+        if !self.runner.cancel_queued(task_id).await {
+            if let Some(stop_signal) = self.runner.stop_signals.lock().await.get(task_id) {
+                stop_signal.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Waits until a task has left the queued and running states or no longer exists.
+    /// `events` must be subscribed before the task is stopped, so that its last update cannot be missed.
+    async fn wait_until_finished(&self, task_id: &str, events: &mut broadcast::Receiver<TaskProgressResponse>) -> Result<(), Status> {
+        loop {
+            match self.runner.tasks.lock().await.get(task_id).map_err(store_error)? {
+                Some(task) if !task.state.is_finished() => {}
+                _ => return Ok(()),
+            }
+            loop {
+                match events.recv().await {
+                    Ok(event) if event.task_id == task_id => break,
+                    Ok(_) => continue,
+                    // Some updates were dropped, look at the current state again
+                    Err(_) => break,
                 }
+            }
+        }
+    }
+
+    async fn spawn_task(&self, task_id: &str, archive_name: &str, options: ArchiveOptions, ttl: Duration, files: Vec<StagedFile>) -> Result<(), Status> {
+        let (files, duplicates) = match options.duplicates.apply(files) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(self.get_upload_dir(task_id)).await;
+                return Err(Status::invalid_argument(e));
+            }
+        };
+        let sealed_password = if options.encryption == Encryption::None {
+            None
+        } else {
+            match self.vault.seal(task_id, &options.password, self.passwords.reveals) {
+                Ok(sealed) => Some(sealed),
                 Err(e) => {
-                    let mut tasks = tasks_clone.lock().await;
-                    if let Some(task) = tasks.get_mut(&task_id_clone) {
-                        task.done = false;
-                        task.error = Some(format!("Failed to create archive: {:?}", e));
-                    }
+                    let _ = tokio::fs::remove_dir_all(self.get_upload_dir(task_id)).await;
+                    return Err(Status::internal(e));
                 }
             }
-        });
+        };
+        let mut task = Task::new(task_id, archive_name, options.format, sealed_password);
+        task.duplicates = duplicates;
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let job = Job {
+            task_id: task_id.to_owned(),
+            file_path: self.get_file_path(task_id, options.format),
+            upload_dir: self.get_upload_dir(task_id),
+            files,
+            options,
+            stop_signal: stop_signal.clone(),
+            ttl,
+        };
+
+        // Workers cannot pick the job up before the task is stored, since updating it requires this lock
+        let mut tasks = self.runner.tasks.lock().await;
+        if !self.runner.queue.push(job) {
+            let _ = tokio::fs::remove_dir_all(self.get_upload_dir(task_id)).await;
+            return Err(queue_full());
+        }
+        if let Err(e) = tasks.put(&task) {
+            self.runner.queue.remove(task_id);
+            let _ = tokio::fs::remove_dir_all(self.get_upload_dir(task_id)).await;
+            return Err(store_error(e));
+        }
+        self.runner.stop_signals.lock().await.insert(task_id.to_owned(), stop_signal);
+
+        Ok(())
+    }
+}
+
+impl From<&Task> for TaskProgressResponse {
+    fn from(task: &Task) -> Self {
+        TaskProgressResponse {
+            task_id: task.taskId.clone(),
+            done: task.state == TaskState::Completed,
+            progress: task.progress,
+            error: task.error.clone().unwrap_or_default(),
+            timestamp: task.timestamp.clone(),
+            archive_name: task.archive_name.clone(),
+            queue_position: 0,
+            bytes_processed: task.bytes_processed,
+            bytes_total: task.bytes_total,
+            current_file: task.current_file.clone(),
+            current_file_bytes_processed: task.current_file_bytes_processed,
+            current_file_bytes_total: task.current_file_bytes_total,
+            password_available: task.sealed_password.is_some(),
+            duplicates: task.duplicates.iter().map(ProtoDuplicateFile::from).collect(),
+            expires_at: task.expires_at.clone().unwrap_or_default(),
+            state: task.state.to_string(),
+            transitions: task
+                .transitions
+                .iter()
+                .map(|transition| StateTransition {
+                    state: transition.state.to_string(),
+                    timestamp: transition.timestamp.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&DuplicateFile> for ProtoDuplicateFile {
+    fn from(duplicate: &DuplicateFile) -> Self {
+        ProtoDuplicateFile {
+            index: duplicate.index as u32,
+            filename: duplicate.filename.clone(),
+            archived_as: duplicate.archived_as.clone(),
+        }
+    }
+}
+
+impl From<ProtoDuplicatePolicy> for DuplicatePolicy {
+    fn from(policy: ProtoDuplicatePolicy) -> Self {
+        match policy {
+            ProtoDuplicatePolicy::Rename => DuplicatePolicy::Rename,
+            ProtoDuplicatePolicy::Error => DuplicatePolicy::Error,
+            ProtoDuplicatePolicy::OverwriteLast => DuplicatePolicy::OverwriteLast,
+            ProtoDuplicatePolicy::KeepFirst => DuplicatePolicy::KeepFirst,
+        }
+    }
+}
+
+impl From<ProtoArchiveFormat> for ArchiveFormat {
+    fn from(format: ProtoArchiveFormat) -> Self {
+        match format {
+            ProtoArchiveFormat::Zip => ArchiveFormat::Zip,
+            ProtoArchiveFormat::Tar => ArchiveFormat::Tar,
+            ProtoArchiveFormat::TarGz => ArchiveFormat::TarGz,
+            ProtoArchiveFormat::TarZst => ArchiveFormat::TarZst,
+            ProtoArchiveFormat::TarXz => ArchiveFormat::TarXz,
+            ProtoArchiveFormat::SevenZ => ArchiveFormat::SevenZ,
+        }
+    }
+}
+
+impl TryFrom<&ProtoCompression> for Compression {
+    type Error = String;
+
+    fn try_from(compression: &ProtoCompression) -> Result<Self, Self::Error> {
+        let method = match ProtoCompressionMethod::try_from(compression.method) {
+            Ok(ProtoCompressionMethod::Deflated) => CompressionMethod::Deflated,
+            Ok(ProtoCompressionMethod::Stored) => CompressionMethod::Stored,
+            Ok(ProtoCompressionMethod::Bzip2) => CompressionMethod::Bzip2,
+            Ok(ProtoCompressionMethod::Zstd) => CompressionMethod::Zstd,
+            Err(_) => return Err(format!("Unknown compression method {}", compression.method)),
+        };
+        let compression = Compression {
+            method,
+            level: compression.level,
+        };
+        compression.validate()?;
+        Ok(compression)
+    }
+}
+
+impl TryFrom<&AllTasksRequest> for TaskQuery {
+    type Error = String;
+
+    fn try_from(request: &AllTasksRequest) -> Result<Self, Self::Error> {
+        let time = |time: &str| match time {
+            "" => Ok(None),
+            _ => parse_timestamp(time)
+                .map(Some)
+                .ok_or_else(|| format!("Invalid time {}, expected RFC 3339", time)),
+        };
+        let newest_first = match SortOrder::try_from(request.sort_order) {
+            Ok(SortOrder::OldestFirst) => false,
+            Ok(SortOrder::NewestFirst) => true,
+            Err(_) => return Err(format!("Unknown sort order {}", request.sort_order)),
+        };
+        Ok(TaskQuery {
+            states: request.states.iter().map(|state| state.parse()).collect::<Result<_, _>>()?,
+            archive_name_prefix: request.archive_name_prefix.clone(),
+            created_after: time(&request.created_after)?,
+            created_before: time(&request.created_before)?,
+            newest_first,
+            page_size: match request.page_size as usize {
+                0 => DEFAULT_PAGE_SIZE,
+                page_size => page_size.min(MAX_PAGE_SIZE),
+            },
+            page_token: match request.page_token.as_str() {
+                "" => None,
+                token => Some(TaskQuery::parse_page_token(token)?),
+            },
+        })
+    }
+}
+
+/// Reads the archive settings of an enqueue request. Encrypted archives get the password of the request
+/// if it satisfies the policy, or a generated one.
+fn archive_options(request: &EnqueueTaskRequest, passwords: &PasswordConfig) -> Result<ArchiveOptions, String> {
+    let format = ArchiveFormat::from(ProtoArchiveFormat::try_from(request.format).map_err(|_| format!("Unknown archive format {}", request.format))?);
+    let encryption = match ProtoEncryption::try_from(request.encryption) {
+        Ok(ProtoEncryption::Default) => Encryption::default_for(format),
+        Ok(ProtoEncryption::None) => Encryption::None,
+        Ok(ProtoEncryption::Aes128) => Encryption::Aes128,
+        Ok(ProtoEncryption::Aes192) => Encryption::Aes192,
+        Ok(ProtoEncryption::Aes256) => Encryption::Aes256,
+        Ok(ProtoEncryption::ZipCrypto) => Encryption::ZipCrypto,
+        Err(_) => return Err(format!("Unknown encryption {}", request.encryption)),
+    };
+    if !encryption.is_supported_by(format) {
+        return Err(format!("{} archives do not support {} encryption", format.extension(), encryption));
+    }
+    let password = match (&request.password, encryption) {
+        (Some(_), Encryption::None) => return Err("A password requires an encrypted archive".into()),
+        (Some(password), _) => {
+            check_password(password, passwords)?;
+            password.clone()
+        }
+        (None, Encryption::None) => String::new(),
+        (None, _) => generate_password(passwords),
+    };
+    let duplicates = ProtoDuplicatePolicy::try_from(request.duplicate_policy)
+        .map_err(|_| format!("Unknown duplicate policy {}", request.duplicate_policy))?
+        .into();
+    let source_date_epoch = match (request.reproducible, request.source_date_epoch) {
+        (true, epoch) => Some(epoch.unwrap_or(DEFAULT_SOURCE_DATE_EPOCH)),
+        (false, Some(_)) => return Err("source_date_epoch requires a reproducible archive".into()),
+        (false, None) => None,
+    };
+    let compression = match &request.compression {
+        Some(compression) => Compression::try_from(compression)?,
+        None => Compression::default(),
+    };
+    Ok(ArchiveOptions {
+        format,
+        encryption,
+        password,
+        compression,
+        duplicates,
+        source_date_epoch,
+    })
+}
+
+/// How long the archive of a task is kept once it is completed.
+fn archive_ttl(request: &EnqueueTaskRequest, retention: &RetentionConfig) -> Result<Duration, String> {
+    match request.ttl {
+        None => Ok(Duration::from_secs(retention.ttl)),
+        Some(0) => Err("ttl must be positive".into()),
+        Some(ttl) if ttl > retention.max_ttl => Err(format!("ttl must not exceed {} seconds", retention.max_ttl)),
+        Some(ttl) => Ok(Duration::from_secs(ttl)),
+    }
+}
+
+/// Entity tag of an archive file. Archives are not modified once they are created, the tag only has
+/// to tell apart files written at different times.
+fn archive_etag(size: u64, modified_at: DateTime<Utc>) -> String {
+    format!("{:x}-{:x}", modified_at.timestamp_micros(), size)
+}
+
+/// No further progress is reported once a task has left the queued and running states.
+fn is_finished(progress: &TaskProgressResponse) -> bool {
+    progress.state != TaskState::Queued.as_str() && progress.state != TaskState::Running.as_str()
+}
+
+/// Writes the files of an `EnqueueTaskStream` upload to the upload directory of the task.
+async fn stage_stream<S>(header: EnqueueTaskRequest, stream: &mut S, stage: &mut UploadStage) -> Result<(), Status>
+where
+    S: Stream<Item = Result<EnqueueTaskChunk, Status>> + Unpin,
+{
+    for file_info in &header.files {
+        stage.add_file(file_info).await.map_err(stage_error)?;
+    }
+
+    while let Some(chunk) = stream.next().await.transpose()? {
+        match chunk.payload {
+            Some(Payload::File(file_info)) => stage.add_file(&file_info).await.map_err(stage_error)?,
+            Some(Payload::Data(data)) => stage.write(&data).await.map_err(stage_error)?,
+            Some(Payload::Header(_)) => return Err(Status::invalid_argument("Header must only be sent once")),
+            Some(Payload::Abort(reason)) => return Err(Status::cancelled(format!("Upload aborted: {}", reason))),
+            None => return Err(Status::invalid_argument("Empty upload chunk")),
+        }
+    }
+
+    Ok(())
+}
+
+fn queue_full() -> Status {
+    Status::resource_exhausted("Task queue is full")
+}
+
+fn store_error(e: io::Error) -> Status {
+    Status::internal(format!("Failed to access task store: {:?}", e))
+}
+
+fn stage_error(e: io::Error) -> Status {
+    match e.kind() {
+        io::ErrorKind::InvalidInput => Status::invalid_argument(e.to_string()),
+        _ => Status::internal(format!("Failed to store uploaded file: {:?}", e)),
+    }
+}
+
+#[tonic::async_trait]
+impl TaskService for TaskServiceImpl {
+    type StreamArchiveStream = ReceiverStream<Result<ArchiveChunk, Status>>;
+    type ReadArchiveStream = ReceiverStream<Result<ArchiveChunk, Status>>;
+    type WatchTaskStream = ReceiverStream<Result<TaskProgressResponse, Status>>;
+    type WatchTasksStream = ReceiverStream<Result<TaskProgressResponse, Status>>;
+
+    async fn enqueue_task(&self, request: Request<EnqueueTaskRequest>) -> Result<Response<TaskIdResponse>, Status> {
+        let req = request.into_inner();
+        let options = archive_options(&req, &self.passwords).map_err(Status::invalid_argument)?;
+        let ttl = archive_ttl(&req, &self.retention).map_err(Status::invalid_argument)?;
+        if self.runner.queue.is_full() {
+            return Err(queue_full());
+        }
+        let task_id = Uuid::new_v4().to_string();
+
+        let mut stage = UploadStage::new(&self.get_upload_dir(&task_id)).await.map_err(stage_error)?;
+        for file_info in &req.files {
+            if let Err(e) = stage.add_file(file_info).await {
+                stage.discard().await;
+                return Err(stage_error(e));
+            }
+        }
+        let files = stage.finish().await.map_err(stage_error)?;
+
+        self.spawn_task(&task_id, &req.archive_name, options, ttl, files).await?;
 
         Ok(Response::new(TaskIdResponse { task_id }))
     }
 
+    async fn enqueue_task_stream(&self, request: Request<Streaming<EnqueueTaskChunk>>) -> Result<Response<TaskIdResponse>, Status> {
+        Ok(Response::new(self.enqueue_chunks(request.into_inner()).await?))
+    }
+
     async fn get_task_progress(&self, request: Request<TaskProgressRequest>) -> Result<Response<TaskProgressResponse>, Status> {
         let task_id = request.into_inner().task_id;
-        let tasks = self.tasks.lock().await;
-        if let Some(task) = tasks.get(&task_id) {
-            Ok(Response::new(TaskProgressResponse {
-                task_id: task.taskId.clone(),
-                done: task.done,
-                progress: task.progress,
-                error: task.error.clone().unwrap_or_default(),
-                timestamp: task.timestamp.clone(),
-                password: task.password.clone(),
-                archive_name: task.archive_name.clone(),
-            }))
-        } else {
-            Err(Status::not_found("Task not found"))
-        }
+        let task = self.find_task(&task_id).await?;
+        Ok(Response::new(self.runner.progress(&task)))
     }
 
-    async fn get_all_tasks(&self, _request: Request<AllTasksRequest>) -> Result<Response<AllTasksResponse>, Status> {
-        let tasks = self.tasks.lock().await;
-        let tasks_list: Vec<TaskProgressResponse> = tasks
-            .values()
-            .map(|task| TaskProgressResponse {
-                task_id: task.taskId.clone(),
-                done: task.done,
-                progress: task.progress,
-                error: task.error.clone().unwrap_or_default(),
-                timestamp: task.timestamp.clone(),
-                password: task.password.clone(),
-                archive_name: task.archive_name.clone(),
-            })
-            .collect();
+    async fn get_all_tasks(&self, request: Request<AllTasksRequest>) -> Result<Response<AllTasksResponse>, Status> {
+        let query = TaskQuery::try_from(&request.into_inner()).map_err(Status::invalid_argument)?;
+        let tasks = self.runner.tasks.lock().await.list().map_err(store_error)?;
+        let page = query.page(tasks);
+        let tasks_list: Vec<TaskProgressResponse> = page.tasks.iter().map(|task| self.runner.progress(task)).collect();
 
-        Ok(Response::new(AllTasksResponse { tasks: tasks_list }))
+        Ok(Response::new(AllTasksResponse {
+            tasks: tasks_list,
+            next_page_token: page.next_page_token.unwrap_or_default(),
+            total_size: page.total_size as u32,
+        }))
     }
 
     async fn stop_task(&self, request: Request<StopTaskRequest>) -> Result<Response<StopTaskResponse>, Status> {
         let task_id = request.into_inner().task_id;
-        let mut tasks = self.tasks.lock().await;
-        if let Some(task) = tasks.get_mut(&task_id) {
-            task.stop_signal.store(true, std::sync::atomic::Ordering::Relaxed);
-            Ok(Response::new(StopTaskResponse {
-                status: "Task stopping".into(),
-            }))
-        } else {
-            Err(Status::not_found("Task not found"))
+        let task = self.find_task(&task_id).await?;
+        if task.state.is_finished() {
+            return Err(Status::failed_precondition(format!("Task is already {}", task.state)));
+        }
+        self.stop(&task_id).await;
+        Ok(Response::new(StopTaskResponse {
+            status: "Task stopping".into(),
+        }))
+    }
+
+    async fn delete_task(&self, request: Request<DeleteTaskRequest>) -> Result<Response<DeleteTaskResponse>, Status> {
+        let task_id = request.into_inner().task_id;
+        let mut events = self.runner.events.subscribe();
+        let task = self.find_task(&task_id).await?;
+        if !task.state.is_finished() {
+            self.stop(&task_id).await;
+            // The worker removes the partial archive before it marks the task as cancelled
+            match tokio::time::timeout(DELETE_STOP_TIMEOUT, self.wait_until_finished(&task_id, &mut events)).await {
+                Ok(result) => result?,
+                Err(_) => return Err(Status::aborted("Task is still stopping, try again later")),
+            }
+        }
+
+        let mut tasks = self.runner.tasks.lock().await;
+        let task = tasks.get(&task_id).map_err(store_error)?.ok_or_else(|| Status::not_found("Task not found"))?;
+        match tokio::fs::remove_file(self.get_file_path(&task_id, task.format)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Status::internal(format!("Failed to delete archive: {:?}", e))),
+            _ => {}
+        }
+        tasks.remove(&task_id).map_err(store_error)?;
+        Ok(Response::new(DeleteTaskResponse {}))
+    }
+
+    async fn reveal_password(&self, request: Request<RevealPasswordRequest>) -> Result<Response<RevealPasswordResponse>, Status> {
+        let task_id = request.into_inner().task_id;
+        let mut tasks = self.runner.tasks.lock().await;
+        let mut task = tasks.get(&task_id).map_err(store_error)?.ok_or_else(|| Status::not_found("Task not found"))?;
+        let Some(mut sealed) = task.sealed_password.take() else {
+            return Err(Status::failed_precondition("Task has no password to reveal"));
+        };
+        let password = self.vault.unseal(&task_id, &sealed).map_err(Status::internal)?;
+        sealed.reveals_left = sealed.reveals_left.saturating_sub(1);
+        let reveals_left = sealed.reveals_left;
+        if reveals_left > 0 {
+            task.sealed_password = Some(sealed);
         }
+        tasks.put(&task).map_err(store_error)?;
+        let _ = self.runner.events.send(self.runner.progress(&task));
+        Ok(Response::new(RevealPasswordResponse { password, reveals_left }))
     }
 
     async fn get_archive(&self, request: Request<GetArchiveRequest>) -> Result<Response<ArchiveResponse>, Status> {
         let task_id = request.into_inner().task_id;
-        let tasks = self.tasks.lock().await;
-        if let Some(task) = tasks.get(&task_id) {
-            let file_path = self.get_file_path(&task_id);
-            let mut file: File = File::open(&file_path).map_err(|e| Status::not_found(format!("File not found: {:?}", e)))?;
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)
-                .map_err(|e| Status::internal(format!("Failed to read file: {:?}", e)))?;
-            Ok(Response::new(ArchiveResponse {
-                archive: buffer,
-                archive_name: task.archive_name.to_owned(),
+        let task = self.find_archive(&task_id).await?;
+        let file_path = self.get_file_path(&task_id, task.format);
+        let mut file: File = File::open(&file_path).map_err(|e| Status::not_found(format!("File not found: {:?}", e)))?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)
+            .map_err(|e| Status::internal(format!("Failed to read file: {:?}", e)))?;
+        Ok(Response::new(ArchiveResponse {
+            archive: buffer,
+            archive_name: task.archive_name,
+            extension: task.format.extension().to_owned(),
+            content_type: task.format.content_type().to_owned(),
+        }))
+    }
+
+    async fn stream_archive(&self, request: Request<GetArchiveRequest>) -> Result<Response<Self::StreamArchiveStream>, Status> {
+        let task_id = request.into_inner().task_id;
+        Ok(Response::new(self.read_archive_file(&task_id, 0, None, None).await?))
+    }
+
+    async fn read_archive(&self, request: Request<ReadArchiveRequest>) -> Result<Response<Self::ReadArchiveStream>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(
+            self.read_archive_file(&request.task_id, request.offset, request.length, request.etag.as_deref())
+                .await?,
+        ))
+    }
+
+    async fn watch_task(&self, request: Request<TaskProgressRequest>) -> Result<Response<Self::WatchTaskStream>, Status> {
+        let task_id = request.into_inner().task_id;
+        // Subscribe before taking the snapshot, so that no update can be missed in between
+        let mut events = self.runner.events.subscribe();
+        let current = self.runner.progress(&self.find_task(&task_id).await?);
+
+        let (tx, rx) = mpsc::channel(16);
+        let runner = self.runner.clone();
+        tokio::spawn(async move {
+            let mut progress = current;
+            loop {
+                let finished = is_finished(&progress);
+                if tx.send(Ok(progress)).await.is_err() || finished {
+                    break;
+                }
+
+                progress = loop {
+                    match events.recv().await {
+                        Ok(event) if event.task_id == task_id => break event,
+                        Ok(_) => continue,
+                        // Some updates were dropped, resynchronize with the current state
+                        Err(broadcast::error::RecvError::Lagged(_)) => match runner.tasks.lock().await.get(&task_id) {
+                            Ok(Some(task)) => break runner.progress(&task),
+                            _ => return,
+                        },
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    }
+                };
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn watch_tasks(&self, request: Request<WatchTasksRequest>) -> Result<Response<Self::WatchTasksStream>, Status> {
+        // All tasks are watched without task IDs
+        let task_ids: Option<HashSet<String>> =
+            Some(request.into_inner().task_ids.into_iter().collect()).filter(|task_ids: &HashSet<String>| !task_ids.is_empty());
+        let mut events = self.runner.events.subscribe();
+
+        let (tx, rx) = mpsc::channel(64);
+        let runner = self.runner.clone();
+        tokio::spawn(async move {
+            loop {
+                let updates = match events.recv().await {
+                    Ok(event) if task_ids.as_ref().is_none_or(|task_ids| task_ids.contains(&event.task_id)) => vec![event],
+                    Ok(_) => continue,
+                    // Some updates were dropped, resynchronize with the current state of the watched tasks
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        let store = runner.tasks.lock().await;
+                        let tasks = match &task_ids {
+                            Some(task_ids) => task_ids.iter().filter_map(|task_id| store.get(task_id).ok().flatten()).collect(),
+                            None => store.list().unwrap_or_default(),
+                        };
+                        tasks.iter().map(|task| runner.progress(task)).collect()
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                for update in updates {
+                    if tx.send(Ok(update)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryTaskStore;
+    use crate::store::TaskStoreKind;
+    use std::path::Path;
+    use task::FileInfo;
+    use tonic::Code;
+
+    fn config(archive_path: &Path) -> TaskServiceConfig {
+        TaskServiceConfig {
+            address: String::new(),
+            max_message_size: "4mb".to_owned(),
+            archive_path: archive_path.to_str().unwrap().to_owned(),
+            simulate_slow_work: false,
+            slow_work_duration: 0,
+            store: TaskStoreKind::Memory,
+            store_path: String::new(),
+            workers: 1,
+            max_queue_length: 4,
+            passwords: PasswordConfig::default(),
+            retention: RetentionConfig::default(),
+        }
+    }
+
+    async fn service(config: &TaskServiceConfig) -> TaskServiceImpl {
+        let service = TaskServiceImpl::new(Box::new(MemoryTaskStore::default()), PasswordVault::open(None).unwrap(), config);
+        service.start().await.unwrap();
+        service
+    }
+
+    async fn enqueue(service: &TaskServiceImpl) -> String {
+        let request = EnqueueTaskRequest {
+            archive_name: "archive".to_owned(),
+            files: vec![FileInfo {
+                filename: "file.txt".to_owned(),
+                content: b"content".to_vec(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        service.enqueue_task(Request::new(request)).await.unwrap().into_inner().task_id
+    }
+
+    async fn progress(service: &TaskServiceImpl, task_id: &str) -> Result<TaskProgressResponse, Status> {
+        let request = Request::new(TaskProgressRequest { task_id: task_id.to_owned() });
+        Ok(service.get_task_progress(request).await?.into_inner())
+    }
+
+    async fn wait_for_state(service: &TaskServiceImpl, task_id: &str, state: TaskState) {
+        let wait = async {
+            while progress(service, task_id).await.unwrap().state != state.as_str() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), wait).await.unwrap();
+    }
+
+    async fn delete(service: &TaskServiceImpl, task_id: &str) -> Result<(), Status> {
+        let request = Request::new(DeleteTaskRequest { task_id: task_id.to_owned() });
+        service.delete_task(request).await.map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn test_password_is_wiped_after_its_reveals() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = config(temp_dir.path());
+        config.passwords.reveals = 2;
+        let service = service(&config).await;
+        let task_id = enqueue(&service).await;
+        let reveal = || service.reveal_password(Request::new(RevealPasswordRequest { task_id: task_id.clone() }));
+
+        let password = reveal().await.unwrap().into_inner();
+        assert_eq!(password.reveals_left, 1);
+        let again = reveal().await.unwrap().into_inner();
+        assert_eq!(again.password, password.password);
+        assert_eq!(again.reveals_left, 0);
+
+        assert!(!progress(&service, &task_id).await.unwrap().password_available);
+        assert_eq!(reveal().await.unwrap_err().code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_delete_completed_task() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let service = service(&config(temp_dir.path())).await;
+        let task_id = enqueue(&service).await;
+        wait_for_state(&service, &task_id, TaskState::Completed).await;
+        let file_path = service.get_file_path(&task_id, ArchiveFormat::Zip);
+        assert!(Path::new(&file_path).exists());
+
+        delete(&service, &task_id).await.unwrap();
+        assert!(!Path::new(&file_path).exists());
+        assert_eq!(progress(&service, &task_id).await.unwrap_err().code(), Code::NotFound);
+        assert_eq!(delete(&service, &task_id).await.unwrap_err().code(), Code::NotFound);
+        assert_eq!(delete(&service, "unknown").await.unwrap_err().code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_delete_stops_queued_and_running_tasks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = config(temp_dir.path());
+        // Ten steps of 200ms with one worker, so the second task waits in the queue
+        config.simulate_slow_work = true;
+        config.slow_work_duration = 2000;
+        let service = service(&config).await;
+        let running = enqueue(&service).await;
+        let queued = enqueue(&service).await;
+        wait_for_state(&service, &running, TaskState::Running).await;
+        assert_eq!(progress(&service, &queued).await.unwrap().state, TaskState::Queued.as_str());
+
+        for task_id in [&queued, &running] {
+            delete(&service, task_id).await.unwrap();
+            assert_eq!(progress(&service, task_id).await.unwrap_err().code(), Code::NotFound);
+        }
+        // Neither the uploads nor a partial archive are left behind
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_aborted_upload_creates_no_task() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let service = service(&config(temp_dir.path())).await;
+        let chunk = |payload| EnqueueTaskChunk { payload: Some(payload) };
+        let chunks = vec![
+            chunk(Payload::Header(EnqueueTaskRequest {
+                archive_name: "archive".to_owned(),
+                ..Default::default()
+            })),
+            chunk(Payload::File(FileInfo {
+                filename: "file.txt".to_owned(),
+                ..Default::default()
+            })),
+            chunk(Payload::Data(b"partial".to_vec())),
+            chunk(Payload::Abort("Failed to read file.txt".to_owned())),
+        ];
+
+        let status = service.enqueue_chunks(tokio_stream::iter(chunks.into_iter().map(Ok))).await.unwrap_err();
+        assert_eq!(status.code(), Code::Cancelled);
+        assert!(service.runner.tasks.lock().await.list().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_watch_tasks_only_sends_watched_tasks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let service = service(&config(temp_dir.path())).await;
+        let watched = enqueue(&service).await;
+        let other = enqueue(&service).await;
+        for task_id in [&watched, &other] {
+            wait_for_state(&service, task_id, TaskState::Completed).await;
+        }
+        let request = Request::new(WatchTasksRequest {
+            task_ids: vec![watched.clone()],
+        });
+        let mut updates = service.watch_tasks(request).await.unwrap().into_inner();
+
+        // More updates than the watcher buffers, so that it lags and resynchronizes
+        let other_progress = service.runner.progress(&service.find_task(&other).await.unwrap());
+        for _ in 0..=TASK_EVENTS_CAPACITY {
+            service.runner.events.send(other_progress.clone()).unwrap();
+        }
+        service.runner.events.send(progress(&service, &watched).await.unwrap()).unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(Some(update)) = tokio::time::timeout(Duration::from_millis(200), updates.next()).await {
+            received.push(update.unwrap().task_id);
+        }
+        // The snapshot taken after the lag, then the last update
+        assert_eq!(received, [watched.clone(), watched]);
+    }
+
+    #[tokio::test]
+    async fn test_read_archive_checks_etag() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let service = service(&config(temp_dir.path())).await;
+        let task_id = enqueue(&service).await;
+        wait_for_state(&service, &task_id, TaskState::Completed).await;
+        let read = |length: Option<u64>, etag: Option<&str>| {
+            service.read_archive(Request::new(ReadArchiveRequest {
+                task_id: task_id.clone(),
+                offset: 0,
+                length,
+                etag: etag.map(str::to_owned),
             }))
-        } else {
-            Err(Status::not_found("Task not found"))
+        };
+
+        let first = read(Some(0), None).await.unwrap().into_inner().next().await.unwrap().unwrap();
+        let mut chunks = read(None, Some(&first.etag)).await.unwrap().into_inner();
+        let mut size = 0;
+        while let Some(chunk) = chunks.next().await {
+            size += chunk.unwrap().data.len() as u64;
         }
+        assert_eq!(size, first.size);
+
+        let status = read(None, Some("other")).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
     }
 }
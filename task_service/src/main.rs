@@ -2,24 +2,18 @@ use api::task::task_service_server::TaskServiceServer;
 use api::TaskServiceImpl;
 use common::parse_size;
 use config::{Config, Environment, File};
-use serde::Deserialize;
+use services::password_vault::PasswordVault;
+use settings::TaskServiceConfig;
 use std::io;
 use tonic::transport::Server;
 
 mod api;
 mod models;
 mod services;
+mod settings;
+mod store;
 mod utils;
 
-#[derive(Deserialize)]
-struct TaskServiceConfig {
-    address: String,
-    max_message_size: String,
-    archive_path: String,
-    simulate_slow_work: bool,
-    slow_work_duration: u64,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let builder = Config::builder();
@@ -27,17 +21,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .add_source(File::with_name("config"))
         .add_source(Environment::with_prefix("APP"))
         .build()
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-    let task_service_config: TaskServiceConfig = settings.get("task_service").map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let task_service_config: TaskServiceConfig = settings.get("task_service").map_err(|e| io::Error::other(e.to_string()))?;
 
     let addr = task_service_config.address.parse()?;
     // Parse max message size
     let max_message_size = parse_size(&task_service_config.max_message_size)?;
-    let task_service = TaskServiceImpl::new(
-        &task_service_config.archive_path,
-        task_service_config.simulate_slow_work,
-        task_service_config.slow_work_duration,
-    );
+    task_service_config.validate().map_err(io::Error::other)?;
+    let store = store::open(task_service_config.store, &task_service_config.store_path)?;
+    let vault = PasswordVault::open(task_service_config.passwords.key_path.as_deref())?;
+    let task_service = TaskServiceImpl::new(store, vault, &task_service_config);
+    task_service.start().await?;
 
     Server::builder()
         .add_service(
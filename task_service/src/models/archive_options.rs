@@ -0,0 +1,18 @@
+use crate::models::archive_format::ArchiveFormat;
+use crate::models::compression::Compression;
+use crate::models::duplicate_policy::DuplicatePolicy;
+use crate::models::encryption::Encryption;
+
+/// Settings the archive of a task is created with.
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveOptions {
+    pub format: ArchiveFormat,
+    pub encryption: Encryption,
+    /// Password the archive is encrypted with, empty without encryption
+    pub password: String,
+    /// Compression of zip entries that neither override it nor are compressed already
+    pub compression: Compression,
+    pub duplicates: DuplicatePolicy,
+    /// Set for reproducible archives, every entry gets this modification time in seconds since the Unix epoch
+    pub source_date_epoch: Option<i64>,
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// How the entries of a zip archive are compressed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionMethod {
+    Stored,
+    #[default]
+    Deflated,
+    Bzip2,
+    Zstd,
+}
+
+impl CompressionMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionMethod::Stored => "stored",
+            CompressionMethod::Deflated => "deflated",
+            CompressionMethod::Bzip2 => "bzip2",
+            CompressionMethod::Zstd => "zstd",
+        }
+    }
+
+    /// Levels accepted by the method, `None` if it has no levels.
+    pub fn level_range(&self) -> Option<RangeInclusive<i32>> {
+        match self {
+            CompressionMethod::Stored => None,
+            CompressionMethod::Deflated | CompressionMethod::Bzip2 => Some(1..=9),
+            CompressionMethod::Zstd => Some(zstd::compression_level_range()),
+        }
+    }
+}
+
+/// Compression method and level of an entry, the method's default level is used when `level` is `None`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Compression {
+    pub method: CompressionMethod,
+    pub level: Option<i32>,
+}
+
+impl Compression {
+    pub fn stored() -> Self {
+        Compression {
+            method: CompressionMethod::Stored,
+            level: None,
+        }
+    }
+
+    /// Checks that the level is supported by the method.
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.level, self.method.level_range()) {
+            (None, _) => Ok(()),
+            (Some(level), Some(range)) if range.contains(&level) => Ok(()),
+            (Some(level), Some(range)) => Err(format!(
+                "Compression level {} is not supported by {}, use {} to {}",
+                level,
+                self.method.as_str(),
+                range.start(),
+                range.end()
+            )),
+            (Some(_), None) => Err(format!("{} compression has no levels", self.method.as_str())),
+        }
+    }
+}
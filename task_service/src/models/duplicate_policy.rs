@@ -0,0 +1,148 @@
+use crate::models::staged_file::StagedFile;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// What to do when several uploaded files have the same path in the archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Reject the task
+    Error,
+    /// Keep every file, later ones get a ` (1)`, ` (2)`, ... suffix before their extension
+    #[default]
+    Rename,
+    /// Keep only the last file uploaded with the name
+    OverwriteLast,
+    /// Keep only the first file uploaded with the name
+    KeepFirst,
+}
+
+/// An uploaded file that was renamed or left out because its name was taken.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateFile {
+    /// Zero-based position of the file in the upload
+    pub index: usize,
+    pub filename: String,
+    /// Name of the file in the archive, `None` if it was left out
+    pub archived_as: Option<String>,
+}
+
+impl DuplicatePolicy {
+    /// Resolves the duplicate names among `files`, returning the files to archive and what happened to the duplicates.
+    pub fn apply(&self, files: Vec<StagedFile>) -> Result<(Vec<StagedFile>, Vec<DuplicateFile>), String> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for file in &files {
+            *counts.entry(file.filename.clone()).or_default() += 1;
+        }
+        if counts.values().all(|&count| count == 1) {
+            return Ok((files, Vec::new()));
+        }
+        if *self == DuplicatePolicy::Error {
+            let mut names: Vec<String> = counts.into_iter().filter(|&(_, count)| count > 1).map(|(name, _)| name).collect();
+            names.sort_unstable();
+            return Err(format!("Duplicate file names: {}", names.join(", ")));
+        }
+
+        // Renamed files must not take the name of any uploaded file, not even a later one
+        let mut taken: HashSet<String> = files.iter().map(|file| file.filename.clone()).collect();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        let mut duplicates = Vec::new();
+        let mut kept = Vec::new();
+        for (index, mut file) in files.into_iter().enumerate() {
+            let occurrence = seen.entry(file.filename.clone()).or_default();
+            *occurrence += 1;
+            let keep = match self {
+                DuplicatePolicy::Rename | DuplicatePolicy::Error => true,
+                DuplicatePolicy::KeepFirst => *occurrence == 1,
+                DuplicatePolicy::OverwriteLast => *occurrence == counts[&file.filename],
+            };
+            if !keep {
+                duplicates.push(DuplicateFile {
+                    index,
+                    filename: file.filename.clone(),
+                    archived_as: None,
+                });
+                continue;
+            }
+            if *self == DuplicatePolicy::Rename && *occurrence > 1 {
+                let name = (1..)
+                    .map(|n| numbered(&file.filename, n))
+                    .find(|name| !taken.contains(name))
+                    .unwrap_or_default();
+                taken.insert(name.clone());
+                duplicates.push(DuplicateFile {
+                    index,
+                    filename: std::mem::replace(&mut file.filename, name.clone()),
+                    archived_as: Some(name),
+                });
+            }
+            kept.push(file);
+        }
+        Ok((kept, duplicates))
+    }
+}
+
+/// Inserts ` (n)` before the extension of the file name, e.g. `photos/a (1).jpg`.
+fn numbered(path: &str, n: usize) -> String {
+    let name_start = path.rfind('/').map_or(0, |index| index + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{} ({}){}", &path[..dot], n, &path[dot..])
+        }
+        _ => format!("{} ({})", path, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn staged(names: &[&str]) -> Vec<StagedFile> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| StagedFile {
+                filename: name.to_string(),
+                path: PathBuf::from(index.to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn kept(files: &[StagedFile]) -> Vec<(String, String)> {
+        files
+            .iter()
+            .map(|file| (file.filename.clone(), file.path.to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let names = ["a.txt", "dir/.env", "a.txt", "a (1).txt", "dir/.env"];
+        assert!(DuplicatePolicy::Error.apply(staged(&names)).is_err());
+        assert!(DuplicatePolicy::Error.apply(staged(&["a.txt", "b.txt"])).is_ok());
+
+        let (files, duplicates) = DuplicatePolicy::Rename.apply(staged(&names)).unwrap();
+        let names: Vec<&str> = files.iter().map(|file| file.filename.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "dir/.env", "a (2).txt", "a (1).txt", "dir/.env (1)"]);
+        assert_eq!(
+            duplicates[0],
+            DuplicateFile {
+                index: 2,
+                filename: "a.txt".into(),
+                archived_as: Some("a (2).txt".into())
+            }
+        );
+
+        let names = ["a.txt", "b.txt", "a.txt"];
+        let (files, duplicates) = DuplicatePolicy::KeepFirst.apply(staged(&names)).unwrap();
+        assert_eq!(kept(&files), vec![("a.txt".into(), "0".into()), ("b.txt".into(), "1".into())]);
+        assert_eq!(duplicates[0].index, 2);
+        assert_eq!(duplicates[0].archived_as, None);
+
+        let (files, duplicates) = DuplicatePolicy::OverwriteLast.apply(staged(&names)).unwrap();
+        assert_eq!(kept(&files), vec![("b.txt".into(), "1".into()), ("a.txt".into(), "2".into())]);
+        assert_eq!(duplicates[0].index, 0);
+    }
+}
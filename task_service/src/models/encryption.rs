@@ -0,0 +1,49 @@
+use crate::models::archive_format::ArchiveFormat;
+use std::fmt;
+
+/// How an archive is protected with its password.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encryption {
+    #[default]
+    None,
+    Aes128,
+    Aes192,
+    Aes256,
+    /// Legacy zip encryption, weak but readable by every unzip tool
+    ZipCrypto,
+}
+
+impl Encryption {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encryption::None => "none",
+            Encryption::Aes128 => "aes-128",
+            Encryption::Aes192 => "aes-192",
+            Encryption::Aes256 => "aes-256",
+            Encryption::ZipCrypto => "zipcrypto",
+        }
+    }
+
+    /// Encryption used when a task does not choose one.
+    pub fn default_for(format: ArchiveFormat) -> Self {
+        match format {
+            ArchiveFormat::Zip | ArchiveFormat::SevenZ => Encryption::Aes256,
+            _ => Encryption::None,
+        }
+    }
+
+    /// Zip supports every mode, 7z only AES-256 and tarballs no encryption at all.
+    pub fn is_supported_by(&self, format: ArchiveFormat) -> bool {
+        match format {
+            ArchiveFormat::Zip => true,
+            ArchiveFormat::SevenZ => matches!(self, Encryption::None | Encryption::Aes256),
+            _ => *self == Encryption::None,
+        }
+    }
+}
+
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1 +1,9 @@
+pub mod archive_format;
+pub mod archive_options;
+pub mod compression;
+pub mod duplicate_policy;
+pub mod encryption;
+pub mod sealed_password;
+pub mod staged_file;
 pub mod task;
+pub mod task_query;
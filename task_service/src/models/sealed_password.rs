@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// An archive password encrypted by the `PasswordVault`, together with the number of times it may still be revealed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SealedPassword {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub reveals_left: u32,
+}
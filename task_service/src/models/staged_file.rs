@@ -0,0 +1,16 @@
+use crate::models::compression::Compression;
+use std::path::PathBuf;
+
+/// An uploaded file that has been written to the upload directory of a task.
+#[derive(Clone, Debug, Default)]
+pub struct StagedFile {
+    /// Normalized path of the file inside the archive
+    pub filename: String,
+    pub path: PathBuf,
+    /// Overrides the compression of the task for this file
+    pub compression: Option<Compression>,
+    /// Seconds since the Unix epoch, the time the archive is created when unknown
+    pub modified_time: Option<i64>,
+    /// Unix permission bits, `DEFAULT_FILE_MODE` when unknown
+    pub mode: Option<u32>,
+}
@@ -1,36 +1,256 @@
-use chrono::Utc;
+use crate::models::archive_format::ArchiveFormat;
+use crate::models::duplicate_policy::DuplicateFile;
+use crate::models::sealed_password::SealedPassword;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::sync::{atomic::AtomicBool, Arc};
+use std::fmt;
+use std::str::FromStr;
+
+/// Lifecycle state of a task.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    #[default]
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    Expired,
+}
+
+impl TaskState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Queued => "queued",
+            TaskState::Running => "running",
+            TaskState::Completed => "completed",
+            TaskState::Failed => "failed",
+            TaskState::Cancelled => "cancelled",
+            TaskState::Expired => "expired",
+        }
+    }
+
+    pub fn can_transition_to(&self, next: TaskState) -> bool {
+        use TaskState::*;
+        matches!(
+            (self, next),
+            (Queued, Running) | (Queued, Failed) | (Queued, Cancelled) | (Running, Completed) | (Running, Failed) | (Running, Cancelled) | (Completed, Expired)
+        )
+    }
+
+    /// A finished task no longer makes progress.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TaskState::Queued | TaskState::Running)
+    }
+}
+
+impl FromStr for TaskState {
+    type Err = String;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        use TaskState::*;
+        [Queued, Running, Completed, Failed, Cancelled, Expired]
+            .into_iter()
+            .find(|candidate| candidate.as_str() == state)
+            .ok_or_else(|| format!("Unknown task state {}", state))
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Time at which a task entered a state.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StateTransition {
+    pub state: TaskState,
+    pub timestamp: String,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 #[allow(non_snake_case)]
 pub struct Task {
     pub taskId: String,
     pub description: Option<String>,
-    pub done: bool,
+    /// Records stored before task states only have `done` and `error`, see [`Task::from_json`]
+    #[serde(default)]
+    pub state: TaskState,
+    #[serde(default)]
+    pub transitions: Vec<StateTransition>,
+    /// Only read from records stored before task states
+    #[serde(default, skip_serializing)]
+    done: bool,
     pub error: Option<String>,
     pub progress: f64,
+    #[serde(default)]
+    pub bytes_processed: u64,
+    #[serde(default)]
+    pub bytes_total: u64,
+    #[serde(default)]
+    pub current_file: String,
+    #[serde(default)]
+    pub current_file_bytes_processed: u64,
+    #[serde(default)]
+    pub current_file_bytes_total: u64,
     pub sourceHash: Option<String>,
     pub timestamp: String,
-    pub password: String,
+    /// Unset for archives without encryption and once the password has been revealed as often as allowed
+    #[serde(default)]
+    pub sealed_password: Option<SealedPassword>,
     pub archive_name: String,
-    #[serde(skip)] // Skip this field during serialization and deserialization
-    pub stop_signal: Arc<AtomicBool>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Uploaded files that were renamed or left out because their name was taken
+    #[serde(default)]
+    pub duplicates: Vec<DuplicateFile>,
+    /// Time at which the archive is deleted, set once the task is completed
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Time at which the archive was last downloaded
+    #[serde(default)]
+    pub last_accessed: Option<String>,
 }
 
 impl Task {
-    pub fn new(task_id: &str, archive_name: &str, password: &str) -> Self {
+    pub fn new(task_id: &str, archive_name: &str, format: ArchiveFormat, sealed_password: Option<SealedPassword>) -> Self {
+        let timestamp = Utc::now().to_rfc3339();
         Task {
             taskId: task_id.to_owned(),
             description: None,
+            state: TaskState::Queued,
+            transitions: vec![StateTransition {
+                state: TaskState::Queued,
+                timestamp: timestamp.clone(),
+            }],
             done: false,
             error: None,
             progress: 0.0,
+            bytes_processed: 0,
+            bytes_total: 0,
+            current_file: String::new(),
+            current_file_bytes_processed: 0,
+            current_file_bytes_total: 0,
             sourceHash: None,
-            timestamp: Utc::now().to_rfc3339(),
-            password: password.to_owned(),
+            timestamp,
+            sealed_password,
             archive_name: archive_name.to_owned(),
-            stop_signal: Arc::new(AtomicBool::new(false)),
+            format,
+            duplicates: Vec::new(),
+            expires_at: None,
+            last_accessed: None,
+        }
+    }
+
+    /// Reads a stored task. Records stored before task states get the state their `done` flag and error
+    /// stand for, entered when the task was created.
+    pub fn from_json(json: &[u8]) -> serde_json::Result<Self> {
+        let mut task: Task = serde_json::from_slice(json)?;
+        if task.transitions.is_empty() {
+            task.state = match (task.done, task.error.as_deref()) {
+                (true, _) => TaskState::Completed,
+                (false, Some("Task stopped")) => TaskState::Cancelled,
+                (false, Some(_)) => TaskState::Failed,
+                (false, None) => TaskState::Queued,
+            };
+            task.transitions.push(StateTransition {
+                state: task.state,
+                timestamp: task.timestamp.clone(),
+            });
+        }
+        Ok(task)
+    }
+
+    /// Moves the task to `state`, recording the time of the transition.
+    pub fn transition(&mut self, state: TaskState) -> Result<(), String> {
+        if !self.state.can_transition_to(state) {
+            return Err(format!("Task cannot move from {} to {}", self.state, state));
         }
+        self.state = state;
+        self.transitions.push(StateTransition {
+            state,
+            timestamp: Utc::now().to_rfc3339(),
+        });
+        Ok(())
+    }
+
+    pub fn fail(&mut self, error: String) -> Result<(), String> {
+        self.transition(TaskState::Failed)?;
+        self.error = Some(error);
+        Ok(())
+    }
+
+    /// Time at which the task entered `state` most recently.
+    pub fn entered(&self, state: TaskState) -> Option<&str> {
+        self.transitions.iter().rev().find(|t| t.state == state).map(|t| t.timestamp.as_str())
+    }
+
+    /// When the archive was last downloaded, or completed if it never was.
+    pub fn last_used(&self) -> Option<DateTime<Utc>> {
+        self.last_accessed
+            .as_deref()
+            .or_else(|| self.entered(TaskState::Completed))
+            .and_then(parse_timestamp)
+    }
+}
+
+/// Parses the RFC 3339 timestamps of tasks.
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let mut task = Task::new("task", "archive", ArchiveFormat::Zip, None);
+        assert!(task.transition(TaskState::Completed).is_err());
+        task.transition(TaskState::Running).unwrap();
+        task.transition(TaskState::Cancelled).unwrap();
+        assert!(task.transition(TaskState::Running).is_err());
+        assert_eq!(task.state, TaskState::Cancelled);
+        let states: Vec<TaskState> = task.transitions.iter().map(|t| t.state).collect();
+        assert_eq!(states, vec![TaskState::Queued, TaskState::Running, TaskState::Cancelled]);
+    }
+
+    #[test]
+    fn test_records_without_states_are_migrated() {
+        let record = |done: bool, error: Option<&str>| {
+            let json = serde_json::json!({
+                "taskId": "task",
+                "description": null,
+                "done": done,
+                "error": error,
+                "progress": 100.0,
+                "sourceHash": null,
+                "timestamp": "2024-01-01T00:00:00+00:00",
+                "password": "password",
+                "archive_name": "archive",
+            });
+            Task::from_json(json.to_string().as_bytes()).unwrap()
+        };
+
+        for (done, error, state) in [
+            (true, None, TaskState::Completed),
+            (false, Some("Failed to create archive"), TaskState::Failed),
+            (false, Some("Task stopped"), TaskState::Cancelled),
+            (false, None, TaskState::Queued),
+        ] {
+            let task = record(done, error);
+            assert_eq!(task.state, state);
+            assert_eq!(task.entered(state), Some("2024-01-01T00:00:00+00:00"));
+            assert_eq!(task.format, ArchiveFormat::Zip);
+            assert!(task.sealed_password.is_none());
+        }
+
+        // Records are stored in the current format again
+        let stored = serde_json::to_vec(&record(true, None)).unwrap();
+        let task = Task::from_json(&stored).unwrap();
+        assert_eq!(task.transitions.len(), 1);
+        assert!(!String::from_utf8(stored).unwrap().contains("\"done\""));
     }
 }
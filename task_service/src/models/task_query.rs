@@ -0,0 +1,144 @@
+use crate::models::task::{parse_timestamp, Task, TaskState};
+use chrono::{DateTime, Utc};
+
+/// Number of tasks per page when the caller does not ask for a page size
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest page size, larger requests are cut down to it
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// Position of a task in a listing: its creation time, with the task ID breaking ties.
+type SortKey = (DateTime<Utc>, String);
+
+/// Filters, order and page of a task listing.
+#[derive(Clone, Debug, Default)]
+pub struct TaskQuery {
+    /// Tasks in any state are listed when empty
+    pub states: Vec<TaskState>,
+    pub archive_name_prefix: String,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub newest_first: bool,
+    pub page_size: usize,
+    /// Key of the last task of the previous page
+    pub page_token: Option<SortKey>,
+}
+
+/// One page of a task listing.
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    /// Unset on the last page
+    pub next_page_token: Option<String>,
+    /// Number of tasks matching the filters on all pages
+    pub total_size: usize,
+}
+
+impl TaskQuery {
+    /// Reads a page token returned in an earlier `TaskPage`.
+    pub fn parse_page_token(token: &str) -> Result<SortKey, String> {
+        let invalid = || format!("Invalid page token {}", token);
+        let (nanos, task_id) = token.split_once('.').ok_or_else(invalid)?;
+        let nanos = nanos.parse().map_err(|_| invalid())?;
+        Ok((DateTime::from_timestamp_nanos(nanos), task_id.to_owned()))
+    }
+
+    pub fn page(&self, tasks: Vec<Task>) -> TaskPage {
+        let mut tasks: Vec<(SortKey, Task)> = tasks
+            .into_iter()
+            .filter(|task| self.matches(task))
+            .map(|task| (sort_key(&task), task))
+            .collect();
+        tasks.sort_by(|(a, _), (b, _)| if self.newest_first { b.cmp(a) } else { a.cmp(b) });
+        let total_size = tasks.len();
+
+        let start = match &self.page_token {
+            Some(token) => tasks.partition_point(|(key, _)| if self.newest_first { key >= token } else { key <= token }),
+            None => 0,
+        };
+        let end = total_size.min(start + self.page_size);
+        let next_page_token = if start < end && end < total_size {
+            let ((created, task_id), _) = &tasks[end - 1];
+            Some(format!("{}.{}", created.timestamp_nanos_opt().unwrap_or_default(), task_id))
+        } else {
+            None
+        };
+        TaskPage {
+            tasks: tasks.drain(start..end).map(|(_, task)| task).collect(),
+            next_page_token,
+            total_size,
+        }
+    }
+
+    fn matches(&self, task: &Task) -> bool {
+        let created = parse_timestamp(&task.timestamp);
+        (self.states.is_empty() || self.states.contains(&task.state))
+            && task.archive_name.starts_with(&self.archive_name_prefix)
+            && self.created_after.is_none_or(|after| created.is_some_and(|created| created > after))
+            && self.created_before.is_none_or(|before| created.is_some_and(|created| created < before))
+    }
+}
+
+fn sort_key(task: &Task) -> SortKey {
+    (parse_timestamp(&task.timestamp).unwrap_or_default(), task.taskId.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::archive_format::ArchiveFormat;
+    use chrono::TimeDelta;
+
+    fn tasks() -> Vec<Task> {
+        let start = Utc::now();
+        ["photos-1", "reports", "photos-2", "photos-3", "photos-4"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let mut task = Task::new(&format!("task-{}", index), name, ArchiveFormat::Zip, None);
+                task.timestamp = (start + TimeDelta::seconds(index as i64)).to_rfc3339();
+                if index == 3 {
+                    task.transition(TaskState::Cancelled).unwrap();
+                }
+                task
+            })
+            .collect()
+    }
+
+    fn ids(page: &TaskPage) -> Vec<&str> {
+        page.tasks.iter().map(|task| task.taskId.as_str()).collect()
+    }
+
+    #[test]
+    fn test_pages_of_filtered_tasks() {
+        let mut query = TaskQuery {
+            states: vec![TaskState::Queued],
+            archive_name_prefix: "photos".to_owned(),
+            page_size: 2,
+            ..Default::default()
+        };
+        let all = tasks();
+        let first = query.page(all.clone());
+        assert_eq!(ids(&first), vec!["task-0", "task-2"]);
+        assert_eq!(first.total_size, 3);
+
+        query.page_token = Some(TaskQuery::parse_page_token(first.next_page_token.as_deref().unwrap()).unwrap());
+        let second = query.page(all.clone());
+        assert_eq!(ids(&second), vec!["task-4"]);
+        assert!(second.next_page_token.is_none());
+
+        query.newest_first = true;
+        query.page_token = None;
+        let first = query.page(all.clone());
+        assert_eq!(ids(&first), vec!["task-4", "task-2"]);
+        query.page_token = Some(TaskQuery::parse_page_token(first.next_page_token.as_deref().unwrap()).unwrap());
+        assert_eq!(ids(&query.page(all.clone())), vec!["task-0"]);
+
+        let query = TaskQuery {
+            created_after: parse_timestamp(&all[0].timestamp),
+            created_before: parse_timestamp(&all[4].timestamp),
+            page_size: 10,
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.page(all)), vec!["task-1", "task-2", "task-3"]);
+        assert!(TaskQuery::parse_page_token("task-1").is_err());
+    }
+}
@@ -0,0 +1,239 @@
+use crate::models::archive_format::ArchiveFormat;
+use crate::models::archive_options::ArchiveOptions;
+use crate::models::compression::{Compression, CompressionMethod};
+use crate::models::encryption::Encryption;
+use crate::services::task_service::ArchiveError;
+use chrono::{Datelike, Timelike};
+use flate2::write::GzEncoder;
+use sevenz_rust2::{encoder_options::AesEncoderOptions, ArchiveEntry, EncoderMethod, NtTime, Password};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xz2::write::XzEncoder;
+use zip::unstable::write::FileOptionsExt;
+use zip::write::{FileOptions, FullFileOptions};
+use zip::{AesMode, ZipWriter};
+
+/// Compression level of xz, the default of the `xz` command line tool.
+const XZ_LEVEL: u32 = 6;
+/// Header ID of the zip extended timestamp extra field
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+/// Unix extension flag of 7z attributes, the unix mode is kept in the upper 16 bits
+const SEVEN_Z_UNIX_EXTENSION: u32 = 0x8000;
+const S_IFREG: u32 = 0o100000;
+const S_IFDIR: u32 = 0o040000;
+
+/// Modification time and permissions of an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// Seconds since the Unix epoch
+    pub modified_time: i64,
+    /// Unix permission bits
+    pub mode: u32,
+}
+
+impl EntryMetadata {
+    fn system_time(&self) -> SystemTime {
+        let seconds = Duration::from_secs(self.modified_time.unsigned_abs());
+        if self.modified_time >= 0 {
+            UNIX_EPOCH + seconds
+        } else {
+            UNIX_EPOCH - seconds
+        }
+    }
+}
+
+/// Writes entries into an archive of a particular format.
+pub trait ArchiveWriter {
+    /// Adds an entry named `name` whose `size` bytes are read from `source`.
+    /// `compression` only applies to formats that compress entries individually.
+    fn add_file(&mut self, name: &str, size: u64, compression: Compression, metadata: EntryMetadata, source: &mut dyn Read) -> Result<(), ArchiveError>;
+
+    /// Adds an empty directory entry named `name`, without a trailing slash.
+    fn add_directory(&mut self, name: &str, metadata: EntryMetadata) -> Result<(), ArchiveError>;
+
+    /// Completes the archive, it is unusable if this is not called.
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError>;
+}
+
+/// Creates the writer for the format of `options` on top of `file`.
+pub fn create_writer(options: &ArchiveOptions, file: File) -> Result<Box<dyn ArchiveWriter + '_>, ArchiveError> {
+    Ok(match options.format {
+        ArchiveFormat::Zip => Box::new(ZipArchiveWriter::new(file, options.encryption, &options.password)),
+        ArchiveFormat::Tar => Box::new(TarArchiveWriter::new(file, |_| Ok(()))),
+        ArchiveFormat::TarGz => Box::new(TarArchiveWriter::new(GzEncoder::new(file, flate2::Compression::default()), |encoder| {
+            encoder.finish().map(drop)
+        })),
+        ArchiveFormat::TarZst => Box::new(TarArchiveWriter::new(zstd::Encoder::new(file, 0)?, |encoder| encoder.finish().map(drop))),
+        ArchiveFormat::TarXz => Box::new(TarArchiveWriter::new(XzEncoder::new(file, XZ_LEVEL), |encoder| encoder.finish().map(drop))),
+        ArchiveFormat::SevenZ => Box::new(SevenZArchiveWriter::new(file, options.encryption, &options.password)?),
+    })
+}
+
+/// Zip archive whose entries are encrypted individually.
+pub struct ZipArchiveWriter<'k> {
+    archive: ZipWriter<File>,
+    options: FullFileOptions<'k>,
+}
+
+impl<'k> ZipArchiveWriter<'k> {
+    pub fn new(file: File, encryption: Encryption, password: &'k str) -> Self {
+        let options = FileOptions::default();
+        let options = match encryption {
+            Encryption::None => options,
+            Encryption::Aes128 => options.with_aes_encryption(AesMode::Aes128, password),
+            Encryption::Aes192 => options.with_aes_encryption(AesMode::Aes192, password),
+            Encryption::Aes256 => options.with_aes_encryption(AesMode::Aes256, password),
+            Encryption::ZipCrypto => options.with_deprecated_encryption(password.as_bytes()),
+        };
+        Self {
+            archive: ZipWriter::new(file),
+            options,
+        }
+    }
+
+    /// Entry options carrying the metadata. The modification time is written as an MS-DOS time in UTC
+    /// and as an extended timestamp, which unzip tools prefer since it is exact and has no time zone.
+    fn entry_options(&self, metadata: EntryMetadata) -> Result<FullFileOptions<'k>, ArchiveError> {
+        let dos_time = chrono::DateTime::from_timestamp(metadata.modified_time, 0)
+            .and_then(|time| {
+                let date = |value: u32| u8::try_from(value).ok();
+                zip::DateTime::from_date_and_time(
+                    u16::try_from(time.year()).ok()?,
+                    date(time.month())?,
+                    date(time.day())?,
+                    date(time.hour())?,
+                    date(time.minute())?,
+                    date(time.second())?,
+                )
+                .ok()
+            })
+            .unwrap_or_default();
+        let mut options = self.options.clone().last_modified_time(dos_time).unix_permissions(metadata.mode);
+        if let Ok(modified_time) = i32::try_from(metadata.modified_time) {
+            let mut data = vec![1];
+            data.extend_from_slice(&modified_time.to_le_bytes());
+            options.add_extra_data(EXTENDED_TIMESTAMP_ID, data.into_boxed_slice(), false)?;
+        }
+        Ok(options)
+    }
+}
+
+impl ArchiveWriter for ZipArchiveWriter<'_> {
+    fn add_file(&mut self, name: &str, _size: u64, compression: Compression, metadata: EntryMetadata, source: &mut dyn Read) -> Result<(), ArchiveError> {
+        let method = match compression.method {
+            CompressionMethod::Stored => zip::CompressionMethod::Stored,
+            CompressionMethod::Deflated => zip::CompressionMethod::Deflated,
+            CompressionMethod::Bzip2 => zip::CompressionMethod::Bzip2,
+            CompressionMethod::Zstd => zip::CompressionMethod::Zstd,
+        };
+        let options = self
+            .entry_options(metadata)?
+            .compression_method(method)
+            .compression_level(compression.level.map(i64::from));
+        self.archive.start_file(name, options)?;
+        io::copy(source, &mut self.archive)?;
+        Ok(())
+    }
+
+    fn add_directory(&mut self, name: &str, metadata: EntryMetadata) -> Result<(), ArchiveError> {
+        self.archive.add_directory(name, self.entry_options(metadata)?)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError> {
+        self.archive.finish()?;
+        Ok(())
+    }
+}
+
+/// Tar archive written through an optional compressor `W`, which `finish_stream` flushes at the end.
+pub struct TarArchiveWriter<W: Write> {
+    builder: tar::Builder<W>,
+    finish_stream: fn(W) -> io::Result<()>,
+}
+
+impl<W: Write> TarArchiveWriter<W> {
+    pub fn new(stream: W, finish_stream: fn(W) -> io::Result<()>) -> Self {
+        Self {
+            builder: tar::Builder::new(stream),
+            finish_stream,
+        }
+    }
+}
+
+/// GNU tar header of an entry, times before the epoch are clamped to it.
+fn tar_header(entry_type: tar::EntryType, size: u64, metadata: EntryMetadata) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(size);
+    header.set_mode(metadata.mode);
+    header.set_mtime(metadata.modified_time.max(0) as u64);
+    header
+}
+
+impl<W: Write> ArchiveWriter for TarArchiveWriter<W> {
+    fn add_file(&mut self, name: &str, size: u64, _compression: Compression, metadata: EntryMetadata, source: &mut dyn Read) -> Result<(), ArchiveError> {
+        let mut header = tar_header(tar::EntryType::Regular, size, metadata);
+        self.builder.append_data(&mut header, name, source)?;
+        Ok(())
+    }
+
+    fn add_directory(&mut self, name: &str, metadata: EntryMetadata) -> Result<(), ArchiveError> {
+        let mut header = tar_header(tar::EntryType::Directory, 0, metadata);
+        self.builder.append_data(&mut header, format!("{}/", name), io::empty())?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError> {
+        let stream = self.builder.into_inner()?;
+        (self.finish_stream)(stream)?;
+        Ok(())
+    }
+}
+
+/// LZMA2 compressed 7z archive. When encrypted, both content and headers are AES-256 encrypted,
+/// so that not even the file names can be read without the password.
+pub struct SevenZArchiveWriter {
+    archive: sevenz_rust2::ArchiveWriter<File>,
+}
+
+impl SevenZArchiveWriter {
+    pub fn new(file: File, encryption: Encryption, password: &str) -> Result<Self, ArchiveError> {
+        let mut archive = sevenz_rust2::ArchiveWriter::new(file)?;
+        if encryption != Encryption::None {
+            archive.set_content_methods(vec![AesEncoderOptions::new(Password::new(password)).into(), EncoderMethod::LZMA2.into()]);
+            archive.set_encrypt_header(true);
+        }
+        Ok(Self { archive })
+    }
+}
+
+impl ArchiveWriter for SevenZArchiveWriter {
+    fn add_file(&mut self, name: &str, _size: u64, _compression: Compression, metadata: EntryMetadata, source: &mut dyn Read) -> Result<(), ArchiveError> {
+        let mut entry = ArchiveEntry::new_file(name);
+        set_seven_z_metadata(&mut entry, metadata, S_IFREG);
+        self.archive.push_archive_entry(entry, Some(source))?;
+        Ok(())
+    }
+
+    fn add_directory(&mut self, name: &str, metadata: EntryMetadata) -> Result<(), ArchiveError> {
+        let mut entry = ArchiveEntry::new_directory(name);
+        set_seven_z_metadata(&mut entry, metadata, S_IFDIR);
+        self.archive.push_archive_entry::<io::Empty>(entry, None)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), ArchiveError> {
+        self.archive.finish()?;
+        Ok(())
+    }
+}
+
+/// Sets the modification time and the unix mode, which 7z keeps in the upper half of the attributes.
+fn set_seven_z_metadata(entry: &mut ArchiveEntry, metadata: EntryMetadata, file_type: u32) {
+    entry.has_last_modified_date = true;
+    entry.last_modified_date = NtTime::try_from(metadata.system_time()).unwrap_or_default();
+    entry.has_windows_attributes = true;
+    entry.windows_attributes |= SEVEN_Z_UNIX_EXTENSION | ((file_type | metadata.mode) << 16);
+}
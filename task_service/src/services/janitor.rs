@@ -0,0 +1,201 @@
+use crate::models::task::{parse_timestamp, Task, TaskState};
+use crate::services::runner::TaskRunner;
+use crate::settings::RetentionConfig;
+use crate::utils::archive_path::archive_file_path;
+use chrono::{TimeDelta, Utc};
+use common::parse_size;
+use std::io;
+use std::time::Duration;
+
+/// Deletes the archives of completed tasks once they expire, and the least recently downloaded ones
+/// early while the archives take up more space than allowed. Their tasks are marked as expired.
+/// Tasks that have no archive anymore are removed from the store once their records expire too.
+pub struct Janitor {
+    runner: TaskRunner,
+    archive_path: String,
+    interval: Duration,
+    max_disk_usage: Option<u64>,
+    record_ttl: TimeDelta,
+}
+
+impl Janitor {
+    pub fn new(runner: TaskRunner, archive_path: &str, retention: &RetentionConfig) -> Self {
+        Self {
+            runner,
+            archive_path: archive_path.to_owned(),
+            interval: Duration::from_secs(retention.cleanup_interval),
+            // The size was checked when the configuration was loaded
+            max_disk_usage: retention
+                .max_disk_usage
+                .as_deref()
+                .and_then(|size| parse_size(size).ok())
+                .map(|size| size as u64),
+            record_ttl: TimeDelta::try_seconds(retention.record_ttl as i64).unwrap_or(TimeDelta::max_value()),
+        }
+    }
+
+    pub fn start(self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.clean_up().await {
+                    eprintln!("Failed to clean up archives: {:?}", e);
+                }
+            }
+        });
+    }
+
+    async fn clean_up(&self) -> io::Result<()> {
+        let now = Utc::now();
+        let tasks = self.runner.tasks.lock().await.list()?;
+        self.remove_records(&tasks).await?;
+        let (expired, kept): (Vec<Task>, Vec<Task>) = tasks
+            .into_iter()
+            .filter(|task| task.state == TaskState::Completed)
+            .partition(|task| task.expires_at.as_deref().and_then(parse_timestamp).is_some_and(|expires_at| expires_at <= now));
+        for task in &expired {
+            self.expire(task).await;
+        }
+
+        let Some(max_disk_usage) = self.max_disk_usage else {
+            return Ok(());
+        };
+        let mut archives = Vec::with_capacity(kept.len());
+        for task in kept {
+            let size = tokio::fs::metadata(self.file_path(&task)).await.map(|metadata| metadata.len()).unwrap_or(0);
+            archives.push((task, size));
+        }
+        let mut disk_usage: u64 = archives.iter().map(|(_, size)| size).sum();
+        archives.sort_by_key(|(task, _)| task.last_used());
+        for (task, size) in archives {
+            if disk_usage <= max_disk_usage {
+                break;
+            }
+            if self.expire(&task).await {
+                disk_usage -= size;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes expired, failed and cancelled tasks from the store once they have been finished for `record_ttl`.
+    async fn remove_records(&self, tasks: &[Task]) -> io::Result<()> {
+        let now = Utc::now();
+        for task in tasks {
+            if !matches!(task.state, TaskState::Expired | TaskState::Failed | TaskState::Cancelled) {
+                continue;
+            }
+            let removed_at = task
+                .entered(task.state)
+                .and_then(parse_timestamp)
+                .and_then(|finished_at| finished_at.checked_add_signed(self.record_ttl));
+            if removed_at.is_some_and(|removed_at| removed_at <= now) {
+                self.runner.tasks.lock().await.remove(&task.taskId)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the archive of a task and marks the task as expired. The task stays completed when the
+    /// archive cannot be deleted, e.g. on Windows while it is being downloaded, so that the next run
+    /// tries again. Returns whether the archive is gone.
+    async fn expire(&self, task: &Task) -> bool {
+        match tokio::fs::remove_file(self.file_path(task)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                eprintln!("Failed to delete archive of task {}: {:?}", task.taskId, e);
+                return false;
+            }
+            _ => {}
+        }
+        self.runner
+            .update_task(&task.taskId, |task| {
+                task.transition(TaskState::Expired)?;
+                task.expires_at = Some(Utc::now().to_rfc3339());
+                Ok(())
+            })
+            .await;
+        true
+    }
+
+    fn file_path(&self, task: &Task) -> String {
+        archive_file_path(&self.archive_path, &task.taskId, task.format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::archive_format::ArchiveFormat;
+    use crate::services::job_queue::JobQueue;
+    use crate::store::memory::MemoryTaskStore;
+    use crate::store::TaskStore;
+    use std::fs;
+    use std::sync::Arc;
+    use tokio::sync::{broadcast, Mutex};
+
+    fn completed(task_id: &str, expires_in: TimeDelta, last_accessed: TimeDelta) -> Task {
+        let mut task = Task::new(task_id, task_id, ArchiveFormat::Zip, None);
+        task.transition(TaskState::Running).unwrap();
+        task.transition(TaskState::Completed).unwrap();
+        task.expires_at = Some((Utc::now() + expires_in).to_rfc3339());
+        task.last_accessed = Some((Utc::now() + last_accessed).to_rfc3339());
+        task
+    }
+
+    fn finished(task_id: &str, state: TaskState, finished_ago: TimeDelta) -> Task {
+        let mut task = Task::new(task_id, task_id, ArchiveFormat::Zip, None);
+        task.transition(state).unwrap();
+        task.transitions.last_mut().unwrap().timestamp = (Utc::now() - finished_ago).to_rfc3339();
+        task
+    }
+
+    #[tokio::test]
+    async fn test_clean_up_expires_and_evicts_archives_and_removes_old_records() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let mut store = MemoryTaskStore::default();
+        let tasks = [
+            completed("expired", TimeDelta::seconds(-1), TimeDelta::zero()),
+            completed("unused", TimeDelta::hours(1), TimeDelta::hours(-2)),
+            completed("used", TimeDelta::hours(1), TimeDelta::hours(-1)),
+            completed("stuck", TimeDelta::seconds(-1), TimeDelta::zero()),
+        ];
+        for task in &tasks {
+            store.put(task).unwrap();
+            fs::write(dir.join(format!("{}.zip", task.taskId)), vec![0; 600]).unwrap();
+        }
+        // An archive that cannot be deleted, as on Windows while it is being downloaded
+        fs::remove_file(dir.join("stuck.zip")).unwrap();
+        fs::create_dir(dir.join("stuck.zip")).unwrap();
+        store.put(&finished("failed", TaskState::Failed, TimeDelta::hours(2))).unwrap();
+        store.put(&finished("cancelled", TaskState::Cancelled, TimeDelta::minutes(1))).unwrap();
+        let runner = TaskRunner::new(
+            Arc::new(Mutex::new(Box::new(store))),
+            Arc::new(JobQueue::new(1)),
+            broadcast::channel(16).0,
+            false,
+            0,
+        );
+        let retention = RetentionConfig {
+            max_disk_usage: Some("1kb".to_owned()),
+            record_ttl: 60 * 60,
+            ..Default::default()
+        };
+
+        Janitor::new(runner.clone(), dir.to_str().unwrap(), &retention).clean_up().await.unwrap();
+
+        let store = runner.tasks.lock().await;
+        for (task_id, state) in [
+            ("expired", TaskState::Expired),
+            ("unused", TaskState::Expired),
+            ("used", TaskState::Completed),
+            ("stuck", TaskState::Completed),
+        ] {
+            assert_eq!(store.get(task_id).unwrap().unwrap().state, state, "{}", task_id);
+            assert_eq!(dir.join(format!("{}.zip", task_id)).exists(), state == TaskState::Completed);
+        }
+        assert!(store.get("failed").unwrap().is_none());
+        assert!(store.get("cancelled").unwrap().is_some());
+    }
+}
@@ -0,0 +1,77 @@
+use crate::models::archive_options::ArchiveOptions;
+use crate::models::staged_file::StagedFile;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Everything a worker needs to create the archive of a task.
+pub struct Job {
+    pub task_id: String,
+    pub file_path: String,
+    pub upload_dir: String,
+    pub files: Vec<StagedFile>,
+    pub options: ArchiveOptions,
+    pub stop_signal: Arc<AtomicBool>,
+    /// How long the archive is kept once it is completed
+    pub ttl: Duration,
+}
+
+/// Bounded FIFO of jobs waiting for a worker.
+pub struct JobQueue {
+    jobs: Mutex<VecDeque<Job>>,
+    available: Notify,
+    max_length: usize,
+}
+
+impl JobQueue {
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            jobs: Mutex::new(VecDeque::new()),
+            available: Notify::new(),
+            max_length,
+        }
+    }
+
+    /// Appends a job, returns `false` and drops it when the queue is full.
+    pub fn push(&self, job: Job) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.len() >= self.max_length {
+            return false;
+        }
+        jobs.push_back(job);
+        self.available.notify_one();
+        true
+    }
+
+    /// Waits for the next job.
+    pub async fn pop(&self) -> Job {
+        loop {
+            if let Some(job) = self.jobs.lock().unwrap().pop_front() {
+                return job;
+            }
+            self.available.notified().await;
+        }
+    }
+
+    pub fn remove(&self, task_id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let index = jobs.iter().position(|job| job.task_id == task_id)?;
+        jobs.remove(index)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.jobs.lock().unwrap().len() >= self.max_length
+    }
+
+    /// One-based position of a task in the queue.
+    pub fn position(&self, task_id: &str) -> Option<usize> {
+        self.jobs.lock().unwrap().iter().position(|job| job.task_id == task_id).map(|index| index + 1)
+    }
+
+    /// IDs of the queued tasks in queue order.
+    pub fn task_ids(&self) -> Vec<String> {
+        self.jobs.lock().unwrap().iter().map(|job| job.task_id.clone()).collect()
+    }
+}
@@ -1 +1,7 @@
+pub mod archive_writer;
+pub mod janitor;
+pub mod job_queue;
+pub mod password_vault;
+pub mod runner;
 pub mod task_service;
+pub mod upload;
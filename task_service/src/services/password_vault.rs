@@ -0,0 +1,115 @@
+use crate::models::sealed_password::SealedPassword;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::fs;
+use std::io::{self, Write};
+
+/// Length of the AES-256 key in bytes
+const KEY_LENGTH: usize = 32;
+
+/// Encrypts archive passwords with AES-256-GCM before they are stored, so that neither the task store
+/// nor anything listing tasks sees them in plain text. The task ID is authenticated along with the
+/// password, a sealed password copied to another task cannot be opened.
+pub struct PasswordVault {
+    cipher: Aes256Gcm,
+}
+
+impl PasswordVault {
+    /// Uses the key stored at `key_path`, which is created with a random key if it does not exist.
+    /// Without a path the key is only kept in memory, so passwords of tasks created before a restart
+    /// cannot be revealed anymore.
+    pub fn open(key_path: Option<&str>) -> io::Result<Self> {
+        let key = match key_path {
+            Some(path) => match fs::read(path) {
+                Ok(key) if key.len() == KEY_LENGTH => *Key::<Aes256Gcm>::from_slice(&key),
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Password key {} must be {} bytes long", path, KEY_LENGTH),
+                    ))
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    let key = Aes256Gcm::generate_key(OsRng);
+                    write_key_file(path, &key)?;
+                    key
+                }
+                Err(e) => return Err(e),
+            },
+            None => Aes256Gcm::generate_key(OsRng),
+        };
+        Ok(Self { cipher: Aes256Gcm::new(&key) })
+    }
+
+    pub fn seal(&self, task_id: &str, password: &str, reveals: u32) -> Result<SealedPassword, String> {
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let payload = Payload {
+            msg: password.as_bytes(),
+            aad: task_id.as_bytes(),
+        };
+        let ciphertext = self.cipher.encrypt(&nonce, payload).map_err(|_| "Failed to encrypt password".to_owned())?;
+        Ok(SealedPassword {
+            nonce: nonce.to_vec(),
+            ciphertext,
+            reveals_left: reveals,
+        })
+    }
+
+    pub fn unseal(&self, task_id: &str, sealed: &SealedPassword) -> Result<String, String> {
+        let payload = Payload {
+            msg: &sealed.ciphertext,
+            aad: task_id.as_bytes(),
+        };
+        let nonce = Nonce::from_exact_iter(sealed.nonce.iter().copied()).ok_or("Invalid password nonce")?;
+        let password = self
+            .cipher
+            .decrypt(&nonce, payload)
+            .map_err(|_| "Password cannot be decrypted with the current key".to_owned())?;
+        String::from_utf8(password).map_err(|_| "Password is not valid UTF-8".to_owned())
+    }
+}
+
+/// Writes the key to a temporary file that is renamed into place once it is complete, so that a failed
+/// write cannot leave a truncated key behind. The file is readable only by its owner where supported.
+fn write_key_file(path: &str, key: &[u8]) -> io::Result<()> {
+    let temp_path = format!("{}.tmp", path);
+    // A leftover of an earlier failed attempt
+    let _ = fs::remove_file(&temp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(key)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_seal_and_unseal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("password.key");
+        let path = path.to_str().unwrap();
+
+        let sealed = PasswordVault::open(Some(path)).unwrap().seal("task", "secret", 1).unwrap();
+        assert_ne!(sealed.ciphertext, b"secret");
+        assert_eq!(fs::read(path).unwrap().len(), KEY_LENGTH);
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        let vault = PasswordVault::open(Some(path)).unwrap();
+        assert_eq!(vault.unseal("task", &sealed).unwrap(), "secret");
+        assert!(vault.unseal("other", &sealed).is_err());
+        assert!(PasswordVault::open(None).unwrap().unseal("task", &sealed).is_err());
+    }
+}
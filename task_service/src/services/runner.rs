@@ -0,0 +1,213 @@
+use crate::api::task::TaskProgressResponse;
+use crate::models::archive_options::ArchiveOptions;
+use crate::models::staged_file::StagedFile;
+use crate::models::task::{Task, TaskState};
+use crate::services::job_queue::{Job, JobQueue};
+use crate::services::task_service::{create_archive, ArchiveError};
+use crate::store::SharedTaskStore;
+use chrono::{TimeDelta, Utc};
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::sleep;
+
+/// Creates the archives of queued tasks on a fixed number of workers and keeps the task records up to date.
+#[derive(Clone)]
+pub struct TaskRunner {
+    pub tasks: SharedTaskStore,
+    pub queue: Arc<JobQueue>,
+    /// Stop signals of the tasks whose archive is still being created
+    pub stop_signals: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    pub events: broadcast::Sender<TaskProgressResponse>,
+    simulate_slow_work: bool,
+    slow_work_duration: u64,
+}
+
+impl TaskRunner {
+    pub fn new(
+        tasks: SharedTaskStore,
+        queue: Arc<JobQueue>,
+        events: broadcast::Sender<TaskProgressResponse>,
+        simulate_slow_work: bool,
+        slow_work_duration: u64,
+    ) -> Self {
+        Self {
+            tasks,
+            queue,
+            stop_signals: Arc::new(Mutex::new(HashMap::new())),
+            events,
+            simulate_slow_work,
+            slow_work_duration,
+        }
+    }
+
+    pub fn start(&self, workers: usize) {
+        for _ in 0..workers {
+            let runner = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    let job = runner.queue.pop().await;
+                    runner.publish_queue_positions().await;
+                    runner.run(job).await;
+                }
+            });
+        }
+    }
+
+    /// Progress of a task, including its position while it waits in the queue.
+    pub fn progress(&self, task: &Task) -> TaskProgressResponse {
+        let mut progress = TaskProgressResponse::from(task);
+        if task.state == TaskState::Queued {
+            progress.queue_position = self.queue.position(&task.taskId).unwrap_or_default() as u32;
+        }
+        progress
+    }
+
+    /// Applies `update` to a task and publishes the new progress to the task watchers.
+    /// The task is left untouched when `update` rejects it, e.g. because it was cancelled meanwhile.
+    pub async fn update_task(&self, task_id: &str, update: impl FnOnce(&mut Task) -> Result<(), String>) {
+        let mut tasks = self.tasks.lock().await;
+        let result = match tasks.get(task_id) {
+            Ok(Some(mut task)) => match update(&mut task) {
+                Ok(()) => tasks.put(&task).map(|_| {
+                    // Sending only fails when nobody is watching
+                    let _ = self.events.send(self.progress(&task));
+                }),
+                Err(_) => Ok(()),
+            },
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to update task {}: {:?}", task_id, e);
+        }
+    }
+
+    /// Lets the watchers of queued tasks know that their queue position changed.
+    pub async fn publish_queue_positions(&self) {
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let tasks = self.tasks.lock().await;
+        for task_id in self.queue.task_ids() {
+            if let Ok(Some(task)) = tasks.get(&task_id) {
+                let _ = self.events.send(self.progress(&task));
+            }
+        }
+    }
+
+    /// Cancels a task that is still waiting in the queue, returns `false` if it already left the queue.
+    pub async fn cancel_queued(&self, task_id: &str) -> bool {
+        let Some(job) = self.queue.remove(task_id) else {
+            return false;
+        };
+        let _ = tokio::fs::remove_dir_all(&job.upload_dir).await;
+        self.stop_signals.lock().await.remove(task_id);
+        self.update_task(task_id, |task| task.transition(TaskState::Cancelled)).await;
+        self.publish_queue_positions().await;
+        true
+    }
+
+    async fn run(&self, job: Job) {
+        let Job {
+            task_id,
+            file_path,
+            upload_dir,
+            files,
+            options,
+            stop_signal,
+            ttl,
+        } = job;
+        self.update_task(&task_id, |task| task.transition(TaskState::Running)).await;
+
+        let mut cancelled = stop_signal.load(Ordering::Relaxed);
+        if self.simulate_slow_work && !cancelled {
+            let total_steps = 10;
+            let step_duration = Duration::from_millis(self.slow_work_duration / total_steps);
+            for step in 1..=total_steps {
+                sleep(step_duration).await;
+                if stop_signal.load(Ordering::Relaxed) {
+                    cancelled = true;
+                    break;
+                }
+
+                let progress = (step as f64 / total_steps as f64) * 100.0;
+                self.update_task(&task_id, |task| {
+                    task.progress = progress;
+                    Ok(())
+                })
+                .await;
+            }
+        }
+
+        let result = if cancelled {
+            Err(ArchiveError::Cancelled)
+        } else {
+            self.build_archive(&task_id, file_path.clone(), files, options, stop_signal).await
+        };
+        let _ = tokio::fs::remove_dir_all(&upload_dir).await;
+        if result.is_err() {
+            // A partially written archive is of no use to anyone
+            let _ = tokio::fs::remove_file(&file_path).await;
+        }
+        self.stop_signals.lock().await.remove(&task_id);
+        match result {
+            Err(ArchiveError::Cancelled) => self.update_task(&task_id, |task| task.transition(TaskState::Cancelled)).await,
+            Ok(_) => {
+                self.update_task(&task_id, |task| {
+                    task.transition(TaskState::Completed)?;
+                    task.progress = 100.0;
+                    task.expires_at = TimeDelta::from_std(ttl)
+                        .ok()
+                        .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                        .map(|time| time.to_rfc3339());
+                    Ok(())
+                })
+                .await
+            }
+            Err(e) => self.update_task(&task_id, |task| task.fail(format!("Failed to create archive: {}", e))).await,
+        }
+    }
+
+    /// Creates the archive on the blocking thread pool, so that the gRPC handlers stay responsive,
+    /// and records the progress it reports.
+    /// The archive builder throttles its reports, so every one of them is stored.
+    async fn build_archive(
+        &self,
+        task_id: &str,
+        file_path: String,
+        files: Vec<StagedFile>,
+        options: ArchiveOptions,
+        stop_signal: Arc<AtomicBool>,
+    ) -> Result<(), ArchiveError> {
+        let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+        let build = tokio::task::spawn_blocking(move || {
+            create_archive(&file_path, files, &options, &stop_signal, |progress| {
+                let _ = progress_tx.send(progress.clone());
+            })
+        });
+
+        // The channel closes once the archive is finished and the sender is dropped
+        while let Some(progress) = progress_rx.recv().await {
+            self.update_task(task_id, |task| {
+                // Progress never goes backwards, e.g. after a simulated slow start
+                task.progress = task.progress.max(progress.percent());
+                task.bytes_processed = progress.bytes_processed;
+                task.bytes_total = progress.bytes_total;
+                task.current_file = progress.current_file;
+                task.current_file_bytes_processed = progress.current_file_bytes_processed;
+                task.current_file_bytes_total = progress.current_file_bytes_total;
+                Ok(())
+            })
+            .await;
+        }
+
+        match build.await {
+            Ok(result) => result,
+            Err(e) => Err(io::Error::other(format!("Archive creation panicked: {}", e)).into()),
+        }
+    }
+}
@@ -1,22 +1,633 @@
-use crate::api::task::FileInfo;
+use crate::models::archive_options::ArchiveOptions;
+use crate::models::compression::Compression;
+use crate::models::staged_file::StagedFile;
+use crate::services::archive_writer::{create_writer, EntryMetadata};
+use crate::utils::entry_path::parent_directories;
+use crate::utils::file_type::{is_compressed, SIGNATURE_LENGTH};
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::File;
-use std::io::Write;
-use zip::{result::ZipError, write::SimpleFileOptions, AesMode, CompressionMethod};
+use std::io::{self, Read, Seek};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zip::result::ZipError;
 
-pub fn create_zip_with_password(file_path: &str, files: Vec<FileInfo>, password: &str) -> Result<(), ZipError> {
-    let file = File::create(file_path)?;
-    let mut archive = zip::ZipWriter::new(file);
+/// Permissions of files that do not come with their own, readable by everyone but not executable.
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+/// Permissions of the directory entries, and of executable files in reproducible archives
+const DIRECTORY_MODE: u32 = 0o755;
 
-    let options = SimpleFileOptions::default()
-        .compression_method(CompressionMethod::Deflated)
-        .with_aes_encryption(AesMode::Aes256, password)
-        .unix_permissions(0o755);
+/// Minimum time between two progress reports within an entry.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
 
-    for file_info in files {
-        archive.start_file(file_info.filename, options)?;
-        archive.write_all(&file_info.content)?;
+/// Reason an archive could not be created.
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// The stop signal was raised while the archive was being written
+    Cancelled,
+    Io(io::Error),
+    Zip(ZipError),
+    SevenZ(sevenz_rust2::Error),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Cancelled => f.write_str("Archive creation was cancelled"),
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::Zip(e) => write!(f, "{}", e),
+            ArchiveError::SevenZ(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(e: ZipError) -> Self {
+        ArchiveError::Zip(e)
+    }
+}
+
+impl From<sevenz_rust2::Error> for ArchiveError {
+    fn from(e: sevenz_rust2::Error) -> Self {
+        ArchiveError::SevenZ(e)
+    }
+}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+/// Progress of an archive being built, in bytes of input fed to the compressor.
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveProgress {
+    pub bytes_processed: u64,
+    pub bytes_total: u64,
+    pub current_file: String,
+    pub current_file_bytes_processed: u64,
+    pub current_file_bytes_total: u64,
+}
+
+impl ArchiveProgress {
+    /// Overall completion as a percentage.
+    pub fn percent(&self) -> f64 {
+        if self.bytes_total == 0 {
+            return 100.0;
+        }
+        self.bytes_processed as f64 / self.bytes_total as f64 * 100.0
+    }
+}
+
+/// Accumulates progress and forwards it to the callback, at most once per `PROGRESS_INTERVAL`
+/// except at entry boundaries.
+struct ProgressTracker<F: FnMut(&ArchiveProgress)> {
+    progress: ArchiveProgress,
+    last_report: Instant,
+    on_progress: F,
+}
+
+impl<F: FnMut(&ArchiveProgress)> ProgressTracker<F> {
+    fn new(bytes_total: u64, on_progress: F) -> Self {
+        ProgressTracker {
+            progress: ArchiveProgress {
+                bytes_total,
+                ..Default::default()
+            },
+            last_report: Instant::now(),
+            on_progress,
+        }
+    }
+
+    fn start_file(&mut self, name: &str, size: u64) {
+        self.progress.current_file = name.to_owned();
+        self.progress.current_file_bytes_processed = 0;
+        self.progress.current_file_bytes_total = size;
+        self.report();
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.progress.bytes_processed += bytes;
+        self.progress.current_file_bytes_processed += bytes;
+        if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        self.last_report = Instant::now();
+        (self.on_progress)(&self.progress);
+    }
+}
+
+/// Reads an entry for the archive writer, reporting the progress and checking the stop signal on every read.
+struct EntryReader<'a, R: Read, F: FnMut(&ArchiveProgress)> {
+    source: R,
+    stop_signal: &'a AtomicBool,
+    tracker: &'a mut ProgressTracker<F>,
+}
+
+impl<R: Read, F: FnMut(&ArchiveProgress)> Read for EntryReader<'_, R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop_signal.load(Ordering::Relaxed) {
+            return Err(io::Error::other(ArchiveError::Cancelled.to_string()));
+        }
+        let read = self.source.read(buf)?;
+        self.tracker.advance(read as u64);
+        Ok(read)
+    }
+}
+
+/// Compression of a zip entry: its own override, `Stored` for files that are compressed already,
+/// or else the compression of the task.
+fn entry_compression(staged: &StagedFile, source: &mut File, options: &ArchiveOptions) -> io::Result<Compression> {
+    if let Some(compression) = staged.compression {
+        return Ok(compression);
+    }
+    let mut head = Vec::with_capacity(SIGNATURE_LENGTH);
+    source.by_ref().take(SIGNATURE_LENGTH as u64).read_to_end(&mut head)?;
+    source.rewind()?;
+    Ok(if is_compressed(&staged.filename, &head) {
+        Compression::stored()
+    } else {
+        options.compression
+    })
+}
+
+/// Modification time and permissions of a staged file's entry. Reproducible archives pin the time
+/// to their epoch and only keep whether a file is executable.
+fn entry_metadata(staged: &StagedFile, options: &ArchiveOptions, now: i64) -> EntryMetadata {
+    let mode = staged.mode.unwrap_or(DEFAULT_FILE_MODE);
+    match options.source_date_epoch {
+        Some(epoch) => EntryMetadata {
+            modified_time: epoch,
+            mode: if mode & 0o111 != 0 { DIRECTORY_MODE } else { DEFAULT_FILE_MODE },
+        },
+        None => EntryMetadata {
+            modified_time: staged.modified_time.unwrap_or(now),
+            mode,
+        },
+    }
+}
+
+/// Writes the staged files into an archive with the given options.
+///
+/// This does blocking file I/O and compression, so it must run on the blocking thread pool.
+/// `on_progress` is called at the start and end of every entry and periodically while an entry is compressed.
+/// `stop_signal` is checked between entries and between reads of an entry; once it is raised the
+/// archive is abandoned with [`ArchiveError::Cancelled`], leaving the partial file for the caller to remove.
+/// Reproducible archives have their entries sorted by path, so that the upload order does not matter.
+pub fn create_archive(
+    file_path: &str,
+    mut files: Vec<StagedFile>,
+    options: &ArchiveOptions,
+    stop_signal: &AtomicBool,
+    on_progress: impl FnMut(&ArchiveProgress),
+) -> Result<(), ArchiveError> {
+    let mut bytes_total = 0;
+    for staged in &files {
+        bytes_total += staged.path.metadata()?.len();
+    }
+    let mut tracker = ProgressTracker::new(bytes_total, on_progress);
+
+    let now = match options.source_date_epoch {
+        Some(epoch) => {
+            files.sort_by(|a, b| a.filename.cmp(&b.filename));
+            epoch
+        }
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default(),
+    };
+    let directory_metadata = EntryMetadata {
+        modified_time: now,
+        mode: DIRECTORY_MODE,
+    };
+
+    let mut archive = create_writer(options, File::create(file_path)?)?;
+    let mut directories = HashSet::new();
+    for staged in files {
+        if stop_signal.load(Ordering::Relaxed) {
+            return Err(ArchiveError::Cancelled);
+        }
+        for directory in parent_directories(&staged.filename) {
+            if directories.insert(directory.to_owned()) {
+                archive.add_directory(directory, directory_metadata)?;
+            }
+        }
+        let mut source = File::open(&staged.path)?;
+        let size = source.metadata()?.len();
+        let compression = entry_compression(&staged, &mut source, options)?;
+        let metadata = entry_metadata(&staged, options, now);
+        tracker.start_file(&staged.filename, size);
+        let mut entry = EntryReader {
+            source,
+            stop_signal,
+            tracker: &mut tracker,
+        };
+        if let Err(e) = archive.add_file(&staged.filename, size, compression, metadata, &mut entry) {
+            // The writer only sees the read error raised by the entry reader
+            return Err(if stop_signal.load(Ordering::Relaxed) { ArchiveError::Cancelled } else { e });
+        }
+        tracker.report();
+    }
+
+    archive.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::archive_format::ArchiveFormat;
+    use crate::models::compression::CompressionMethod;
+    use crate::models::encryption::Encryption;
+    use std::fs;
+
+    fn options(format: ArchiveFormat, password: &str) -> ArchiveOptions {
+        ArchiveOptions {
+            format,
+            encryption: if password.is_empty() {
+                Encryption::None
+            } else {
+                Encryption::default_for(format)
+            },
+            password: password.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_progress_counts_input_bytes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let mut files = Vec::new();
+        for (index, size) in [200_017, 0, 1000].into_iter().enumerate() {
+            let path = dir.join(index.to_string());
+            fs::write(&path, vec![b'a'; size]).unwrap();
+            files.push(StagedFile {
+                filename: format!("file{}.txt", index),
+                path,
+                ..Default::default()
+            });
+        }
+
+        let mut reports = Vec::new();
+        let file_path = dir.join("archive.zip");
+        create_archive(
+            file_path.to_str().unwrap(),
+            files,
+            &options(ArchiveFormat::Zip, "password"),
+            &AtomicBool::new(false),
+            |progress| reports.push(progress.clone()),
+        )
+        .unwrap();
+
+        let last = reports.last().unwrap();
+        assert_eq!(last.bytes_total, 201_017);
+        assert_eq!(last.bytes_processed, last.bytes_total);
+        assert_eq!(last.current_file, "file2.txt");
+        assert_eq!(last.current_file_bytes_processed, 1000);
+        assert!(reports.windows(2).all(|pair| pair[0].bytes_processed <= pair[1].bytes_processed));
+    }
+
+    #[test]
+    fn test_stop_signal_aborts_archive() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let path = dir.join("0");
+        fs::write(&path, vec![b'a'; 200_000]).unwrap();
+        let files = vec![StagedFile {
+            filename: "file.txt".to_owned(),
+            path,
+            ..Default::default()
+        }];
+
+        let stop_signal = AtomicBool::new(false);
+        let mut reports = 0;
+        let file_path = dir.join("archive.tar.gz");
+        let result = create_archive(file_path.to_str().unwrap(), files, &options(ArchiveFormat::TarGz, ""), &stop_signal, |_| {
+            // Raised once the first entry has started
+            reports += 1;
+            stop_signal.store(true, Ordering::Relaxed);
+        });
+
+        assert!(matches!(result, Err(ArchiveError::Cancelled)));
+        assert_eq!(reports, 1);
+    }
+
+    #[test]
+    fn test_tarballs_contain_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let contents = [b"first".to_vec(), vec![7; 100_000]];
+        for (index, content) in contents.iter().enumerate() {
+            fs::write(dir.join(index.to_string()), content).unwrap();
+        }
+
+        for format in [ArchiveFormat::Tar, ArchiveFormat::TarGz, ArchiveFormat::TarZst, ArchiveFormat::TarXz] {
+            let files = (0..contents.len())
+                .map(|index| StagedFile {
+                    filename: format!("file{}.bin", index),
+                    path: dir.join(index.to_string()),
+                    ..Default::default()
+                })
+                .collect();
+            let file_path = dir.join(format!("archive.{}", format.extension()));
+            create_archive(file_path.to_str().unwrap(), files, &options(format, ""), &AtomicBool::new(false), |_| {}).unwrap();
+
+            let file = File::open(&file_path).unwrap();
+            let stream: Box<dyn Read> = match format {
+                ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+                ArchiveFormat::TarZst => Box::new(zstd::Decoder::new(file).unwrap()),
+                ArchiveFormat::TarXz => Box::new(xz2::read::XzDecoder::new(file)),
+                _ => Box::new(file),
+            };
+            let mut archive = tar::Archive::new(stream);
+            let mut entries = Vec::new();
+            for entry in archive.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                entries.push((entry.path().unwrap().to_string_lossy().into_owned(), content));
+            }
+            assert_eq!(
+                entries,
+                vec![("file0.bin".to_owned(), contents[0].clone()), ("file1.bin".to_owned(), contents[1].clone())],
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_directories_are_added() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("0"), b"content").unwrap();
+        let staged = |filename: &str| StagedFile {
+            filename: filename.to_owned(),
+            path: dir.join("0"),
+            ..Default::default()
+        };
+
+        let file_path = dir.join("archive.zip");
+        let files = vec![staged("photos/2024/a.jpg"), staged("photos/b.jpg"), staged("c.txt")];
+        create_archive(
+            file_path.to_str().unwrap(),
+            files,
+            &options(ArchiveFormat::Zip, ""),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .unwrap();
+        let archive = zip::ZipArchive::new(File::open(&file_path).unwrap()).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert_eq!(names, vec!["photos/", "photos/2024/", "photos/2024/a.jpg", "photos/b.jpg", "c.txt"]);
+
+        let file_path = dir.join("archive.tar");
+        let files = vec![staged("photos/2024/a.jpg")];
+        create_archive(
+            file_path.to_str().unwrap(),
+            files,
+            &options(ArchiveFormat::Tar, ""),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .unwrap();
+        let mut archive = tar::Archive::new(File::open(&file_path).unwrap());
+        let entries: Vec<(String, bool)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path().unwrap().to_string_lossy().into_owned(), entry.header().entry_type().is_dir())
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("photos/".to_owned(), true),
+                ("photos/2024/".to_owned(), true),
+                ("photos/2024/a.jpg".to_owned(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metadata_is_preserved() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("0"), b"content").unwrap();
+        let files = || {
+            vec![
+                StagedFile {
+                    filename: "run.sh".to_owned(),
+                    path: dir.join("0"),
+                    modified_time: Some(1_600_000_000),
+                    mode: Some(0o755),
+                    ..Default::default()
+                },
+                StagedFile {
+                    filename: "notes.txt".to_owned(),
+                    path: dir.join("0"),
+                    ..Default::default()
+                },
+            ]
+        };
+
+        let file_path = dir.join("archive.zip");
+        create_archive(
+            file_path.to_str().unwrap(),
+            files(),
+            &options(ArchiveFormat::Zip, ""),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(&file_path).unwrap()).unwrap();
+        let entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.unix_mode().unwrap() & 0o7777, 0o755);
+        let zip::ExtraField::ExtendedTimestamp(timestamp) = entry.extra_data_fields().next().unwrap();
+        assert_eq!(timestamp.mod_time(), Some(1_600_000_000));
+        let modified = entry.last_modified().unwrap();
+        assert_eq!((modified.year(), modified.month(), modified.day()), (2020, 9, 13));
+        drop(entry);
+        assert_eq!(archive.by_index(1).unwrap().unix_mode().unwrap() & 0o7777, DEFAULT_FILE_MODE);
+
+        let file_path = dir.join("archive.tar");
+        create_archive(
+            file_path.to_str().unwrap(),
+            files(),
+            &options(ArchiveFormat::Tar, ""),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .unwrap();
+        let mut archive = tar::Archive::new(File::open(&file_path).unwrap());
+        let headers: Vec<(u32, u64)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let header = entry.unwrap().header().clone();
+                (header.mode().unwrap(), header.mtime().unwrap())
+            })
+            .collect();
+        assert_eq!(headers[0], (0o755, 1_600_000_000));
+        assert_eq!(headers[1].0, DEFAULT_FILE_MODE);
     }
 
-    archive.finish()?;
-    Ok(())
+    #[test]
+    fn test_reproducible_archives_are_identical() {
+        use sha2::{Digest, Sha256};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("0"), b"first").unwrap();
+        fs::write(dir.join("1"), vec![7; 100_000]).unwrap();
+        let files = |order: [usize; 2], modified_time: i64| {
+            order
+                .into_iter()
+                .map(|index| StagedFile {
+                    filename: format!("dir/file{}.bin", index),
+                    path: dir.join(index.to_string()),
+                    modified_time: Some(modified_time),
+                    mode: Some(0o600 + index as u32 * 0o100),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for format in [
+            ArchiveFormat::Zip,
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+            ArchiveFormat::TarXz,
+            ArchiveFormat::SevenZ,
+        ] {
+            let options = ArchiveOptions {
+                source_date_epoch: Some(1_700_000_000),
+                ..options(format, "")
+            };
+            let hashes: Vec<_> = [([0, 1], 1_600_000_000), ([1, 0], 1_650_000_000)]
+                .into_iter()
+                .map(|(order, modified_time)| {
+                    let file_path = dir.join(format!("archive.{}", format.extension()));
+                    create_archive(
+                        file_path.to_str().unwrap(),
+                        files(order, modified_time),
+                        &options,
+                        &AtomicBool::new(false),
+                        |_| {},
+                    )
+                    .unwrap();
+                    Sha256::digest(fs::read(&file_path).unwrap())
+                })
+                .collect();
+            assert_eq!(hashes[0], hashes[1], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_seven_z_hides_file_names() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let path = dir.join("0");
+        fs::write(&path, b"secret content").unwrap();
+        let files = vec![StagedFile {
+            filename: "secret-name.txt".to_owned(),
+            path,
+            ..Default::default()
+        }];
+
+        let file_path = dir.join("archive.7z");
+        create_archive(
+            file_path.to_str().unwrap(),
+            files,
+            &options(ArchiveFormat::SevenZ, "password"),
+            &AtomicBool::new(false),
+            |_| {},
+        )
+        .unwrap();
+
+        assert!(sevenz_rust2::ArchiveReader::open(&file_path, sevenz_rust2::Password::empty()).is_err());
+        let mut reader = sevenz_rust2::ArchiveReader::open(&file_path, sevenz_rust2::Password::new("password")).unwrap();
+        assert_eq!(reader.read_file("secret-name.txt").unwrap(), b"secret content");
+    }
+
+    #[test]
+    fn test_compressed_files_are_stored() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let entries = [
+            ("notes.txt", b"plain text".to_vec(), None),
+            ("photo.png", b"not really a png".to_vec(), None),
+            ("image", b"\xFF\xD8\xFF\xE0 jpeg data".to_vec(), None),
+            ("data.bin", b"forced".to_vec(), Some(Compression::stored())),
+        ];
+        let mut files = Vec::new();
+        for (index, (filename, content, compression)) in entries.iter().enumerate() {
+            let path = dir.join(index.to_string());
+            fs::write(&path, content).unwrap();
+            files.push(StagedFile {
+                filename: filename.to_string(),
+                path,
+                compression: *compression,
+                ..Default::default()
+            });
+        }
+
+        let file_path = dir.join("archive.zip");
+        let mut options = options(ArchiveFormat::Zip, "");
+        options.compression = Compression {
+            method: CompressionMethod::Bzip2,
+            level: Some(9),
+        };
+        create_archive(file_path.to_str().unwrap(), files, &options, &AtomicBool::new(false), |_| {}).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&file_path).unwrap()).unwrap();
+        let methods: Vec<zip::CompressionMethod> = (0..archive.len()).map(|index| archive.by_index(index).unwrap().compression()).collect();
+        assert_eq!(
+            methods,
+            vec![
+                zip::CompressionMethod::Bzip2,
+                zip::CompressionMethod::Stored,
+                zip::CompressionMethod::Stored,
+                zip::CompressionMethod::Stored
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zip_encryption_modes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let path = dir.join("0");
+        fs::write(&path, b"content").unwrap();
+
+        for encryption in [
+            Encryption::None,
+            Encryption::Aes128,
+            Encryption::Aes192,
+            Encryption::Aes256,
+            Encryption::ZipCrypto,
+        ] {
+            let files = vec![StagedFile {
+                filename: "file.txt".to_owned(),
+                path: path.clone(),
+                ..Default::default()
+            }];
+            let options = ArchiveOptions {
+                encryption,
+                password: "password".to_owned(),
+                ..Default::default()
+            };
+            let file_path = dir.join("archive.zip");
+            create_archive(file_path.to_str().unwrap(), files, &options, &AtomicBool::new(false), |_| {}).unwrap();
+
+            let mut archive = zip::ZipArchive::new(File::open(&file_path).unwrap()).unwrap();
+            assert_eq!(archive.by_index(0).is_ok(), encryption == Encryption::None, "{}", encryption);
+            let mut content = String::new();
+            archive.by_index_decrypt(0, b"password").unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, "content", "{}", encryption);
+        }
+    }
 }
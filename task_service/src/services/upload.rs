@@ -0,0 +1,86 @@
+use crate::api::task::FileInfo;
+use crate::models::compression::Compression;
+use crate::models::staged_file::StagedFile;
+use crate::utils::entry_path::normalize_path;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+/// Permission bits a file mode may contain, including setuid, setgid and sticky
+const MODE_BITS: u32 = 0o7777;
+
+/// Writes uploaded files to disk as they arrive so that large uploads are never held in memory.
+pub struct UploadStage {
+    dir: PathBuf,
+    files: Vec<StagedFile>,
+    current: Option<File>,
+}
+
+impl UploadStage {
+    pub async fn new(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir).await?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            files: Vec::new(),
+            current: None,
+        })
+    }
+
+    /// Starts a new file; subsequent calls to `write` append to it.
+    /// `filename` may be a relative path, which becomes the path of the file inside the archive.
+    pub async fn start_file(&mut self, filename: &str, compression: Option<Compression>, modified_time: Option<i64>, mode: Option<u32>) -> io::Result<()> {
+        let filename = normalize_path(filename).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if let Some(mode) = mode.filter(|mode| mode & !MODE_BITS != 0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid mode {:o} of {}", mode, filename)));
+        }
+        self.close_current().await?;
+        let path = self.dir.join(self.files.len().to_string());
+        self.current = Some(File::create(&path).await?);
+        self.files.push(StagedFile {
+            filename,
+            path,
+            compression,
+            modified_time,
+            mode,
+        });
+        Ok(())
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.current.as_mut() {
+            Some(file) => file.write_all(data).await,
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "File data received before file info")),
+        }
+    }
+
+    pub async fn add_file(&mut self, file_info: &FileInfo) -> io::Result<()> {
+        let compression = file_info
+            .compression
+            .as_ref()
+            .map(Compression::try_from)
+            .transpose()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.start_file(&file_info.filename, compression, file_info.modified_time, file_info.mode)
+            .await?;
+        self.write(&file_info.content).await
+    }
+
+    pub async fn finish(mut self) -> io::Result<Vec<StagedFile>> {
+        self.close_current().await?;
+        Ok(self.files)
+    }
+
+    /// Removes everything written so far, used when an upload is aborted.
+    pub async fn discard(mut self) {
+        self.current.take();
+        let _ = fs::remove_dir_all(&self.dir).await;
+    }
+
+    async fn close_current(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.current.take() {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+}
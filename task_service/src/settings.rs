@@ -0,0 +1,153 @@
+use crate::store::TaskStoreKind;
+use common::parse_size;
+use serde::Deserialize;
+
+/// The `[task_service]` section of `config.toml`.
+#[derive(Deserialize, Clone)]
+pub struct TaskServiceConfig {
+    pub address: String,
+    pub max_message_size: String,
+    pub archive_path: String,
+    pub simulate_slow_work: bool,
+    pub slow_work_duration: u64,
+    pub store: TaskStoreKind,
+    pub store_path: String,
+    /// Number of archives created concurrently
+    pub workers: usize,
+    /// Number of tasks that may wait for a worker before new tasks are rejected
+    pub max_queue_length: usize,
+    #[serde(default)]
+    pub passwords: PasswordConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+impl TaskServiceConfig {
+    /// Checks the settings, including the password and retention sections.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.workers == 0 {
+            return Err("Number of workers must be positive".into());
+        }
+        if self.max_queue_length == 0 {
+            return Err("Maximum queue length must be positive".into());
+        }
+        self.passwords.validate()?;
+        self.retention.validate()
+    }
+}
+
+/// The optional `[task_service.retention]` section of `config.toml`, covering how long archives are kept.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Seconds a completed archive is kept, unless its task asks for another time
+    pub ttl: u64,
+    /// Longest time in seconds that a task may ask for
+    pub max_ttl: u64,
+    /// Total size of the completed archives, e.g. "20gb", beyond which the least recently downloaded ones are deleted early
+    pub max_disk_usage: Option<String>,
+    /// Seconds between two runs of the cleanup
+    pub cleanup_interval: u64,
+    /// Seconds expired, failed and cancelled tasks are kept in the store before they are removed
+    pub record_ttl: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 24 * 60 * 60,
+            max_ttl: 7 * 24 * 60 * 60,
+            max_disk_usage: None,
+            cleanup_interval: 60,
+            record_ttl: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ttl == 0 {
+            return Err("Archive TTL must be positive".into());
+        }
+        if self.max_ttl < self.ttl {
+            return Err("Maximum archive TTL must not be shorter than the TTL".into());
+        }
+        if self.cleanup_interval == 0 {
+            return Err("Cleanup interval must be positive".into());
+        }
+        if let Some(size) = &self.max_disk_usage {
+            parse_size(size).map_err(|e| format!("Invalid maximum disk usage {}: {}", size, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// How archive passwords are generated.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordGenerator {
+    /// Characters picked from the alphabet
+    Random,
+    /// Words picked from the EFF large wordlist
+    Diceware,
+}
+
+/// The optional `[task_service.passwords]` section of `config.toml`, covering generated passwords
+/// and the policy that passwords chosen by callers must satisfy.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct PasswordConfig {
+    pub generator: PasswordGenerator,
+    /// Number of characters of random passwords, or number of words of diceware passphrases
+    pub length: usize,
+    /// Characters of random passwords
+    pub alphabet: String,
+    /// Separator between the words of diceware passphrases
+    pub separator: String,
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Passwords that callers may not choose, compared case-insensitively
+    pub banned: Vec<String>,
+    /// Number of times the password of a task can be revealed before it is wiped
+    pub reveals: u32,
+    /// File holding the key that passwords are encrypted with at rest, created if missing.
+    /// Without it passwords cannot be revealed after a restart
+    pub key_path: Option<String>,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            generator: PasswordGenerator::Random,
+            length: 20,
+            alphabet: "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789".to_owned(),
+            separator: "-".to_owned(),
+            min_length: 12,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            banned: Vec::new(),
+            reveals: 1,
+            key_path: None,
+        }
+    }
+}
+
+impl PasswordConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.length == 0 {
+            return Err("Generated password length must be positive".into());
+        }
+        if self.reveals == 0 {
+            return Err("Password reveals must be positive".into());
+        }
+        if self.generator == PasswordGenerator::Random && self.alphabet.is_empty() {
+            return Err("Password alphabet must not be empty".into());
+        }
+        Ok(())
+    }
+}
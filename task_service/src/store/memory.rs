@@ -0,0 +1,28 @@
+use super::TaskStore;
+use crate::models::task::Task;
+use std::collections::HashMap;
+use std::io;
+
+#[derive(Default)]
+pub struct MemoryTaskStore {
+    tasks: HashMap<String, Task>,
+}
+
+impl TaskStore for MemoryTaskStore {
+    fn get(&self, task_id: &str) -> io::Result<Option<Task>> {
+        Ok(self.tasks.get(task_id).cloned())
+    }
+
+    fn list(&self) -> io::Result<Vec<Task>> {
+        Ok(self.tasks.values().cloned().collect())
+    }
+
+    fn put(&mut self, task: &Task) -> io::Result<()> {
+        self.tasks.insert(task.taskId.clone(), task.clone());
+        Ok(())
+    }
+
+    fn remove(&mut self, task_id: &str) -> io::Result<Option<Task>> {
+        Ok(self.tasks.remove(task_id))
+    }
+}
@@ -0,0 +1,36 @@
+use crate::models::task::Task;
+use serde::Deserialize;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+pub mod memory;
+pub mod sled;
+
+/// Task store shared between the gRPC handlers and the running jobs.
+/// The mutex also makes read-modify-write updates of a task atomic.
+pub type SharedTaskStore = Arc<Mutex<Box<dyn TaskStore>>>;
+
+/// Storage for task records.
+pub trait TaskStore: Send + Sync {
+    fn get(&self, task_id: &str) -> io::Result<Option<Task>>;
+    fn list(&self) -> io::Result<Vec<Task>>;
+    fn put(&mut self, task: &Task) -> io::Result<()>;
+    fn remove(&mut self, task_id: &str) -> io::Result<Option<Task>>;
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStoreKind {
+    /// Tasks are lost when the service restarts
+    Memory,
+    /// Tasks are kept in an embedded sled database at `store_path`
+    Sled,
+}
+
+pub fn open(kind: TaskStoreKind, store_path: &str) -> io::Result<Box<dyn TaskStore>> {
+    match kind {
+        TaskStoreKind::Memory => Ok(Box::new(memory::MemoryTaskStore::default())),
+        TaskStoreKind::Sled => Ok(Box::new(sled::SledTaskStore::open(store_path)?)),
+    }
+}
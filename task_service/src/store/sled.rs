@@ -0,0 +1,79 @@
+use super::TaskStore;
+use crate::models::task::Task;
+use std::io;
+
+/// Keeps tasks as JSON documents keyed by task ID in an embedded sled database.
+pub struct SledTaskStore {
+    db: sled::Db,
+}
+
+impl SledTaskStore {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(Self { db: sled::open(path)? })
+    }
+}
+
+impl TaskStore for SledTaskStore {
+    fn get(&self, task_id: &str) -> io::Result<Option<Task>> {
+        match self.db.get(task_id)? {
+            Some(value) => Ok(Some(Task::from_json(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self) -> io::Result<Vec<Task>> {
+        self.db.iter().values().map(|value| Ok(Task::from_json(&value?)?)).collect()
+    }
+
+    fn put(&mut self, task: &Task) -> io::Result<()> {
+        self.db.insert(&task.taskId, serde_json::to_vec(task)?)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, task_id: &str) -> io::Result<Option<Task>> {
+        match self.db.remove(task_id)? {
+            Some(value) => Ok(Some(Task::from_json(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::archive_format::ArchiveFormat;
+    use crate::models::task::TaskState;
+
+    #[test]
+    fn test_tasks_survive_reopen() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("tasks.db");
+        let path = path.to_str().unwrap();
+        let mut task = Task::new("task", "archive", ArchiveFormat::Zip, None);
+
+        {
+            let mut store = SledTaskStore::open(path).unwrap();
+            store.put(&task).unwrap();
+            task.transition(TaskState::Running).unwrap();
+            store.put(&task).unwrap();
+            store.put(&Task::new("removed", "archive", ArchiveFormat::Zip, None)).unwrap();
+            assert!(store.remove("removed").unwrap().is_some());
+        }
+
+        let store = reopen(path);
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(store.get("task").unwrap().unwrap().state, TaskState::Running);
+        assert!(store.get("removed").unwrap().is_none());
+    }
+
+    /// sled's background flusher may hold the file lock for a moment after the database is dropped.
+    fn reopen(path: &str) -> SledTaskStore {
+        for _ in 0..50 {
+            if let Ok(store) = SledTaskStore::open(path) {
+                return store;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        SledTaskStore::open(path).unwrap()
+    }
+}
@@ -0,0 +1,6 @@
+use crate::models::archive_format::ArchiveFormat;
+
+/// Path of the archive of a task in the archive directory.
+pub fn archive_file_path(archive_path: &str, task_id: &str, format: ArchiveFormat) -> String {
+    format!("{}/{}.{}", archive_path, task_id, format.extension())
+}
@@ -0,0 +1,60 @@
+/// Turns the relative path of an uploaded file into the name of its archive entry.
+///
+/// Backslashes are treated as separators and empty and `.` components are dropped, so that
+/// `./photos//2024\a.jpg` becomes `photos/2024/a.jpg`. Paths that could escape the directory
+/// an archive is extracted to, i.e. absolute paths, drive letters and `..` components, are rejected.
+pub fn normalize_path(path: &str) -> Result<String, String> {
+    let path = path.replace('\\', "/");
+    if path.starts_with('/') {
+        return Err(format!("Absolute path {} is not allowed", path));
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return Err(format!("Path {} must not start with a drive letter", path));
+    }
+
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(format!("Path {} must not contain ..", path)),
+            _ if component.contains('\0') => return Err("Path must not contain NUL characters".into()),
+            _ => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return Err("Path must name a file".into());
+    }
+    Ok(components.join("/"))
+}
+
+/// Parent directories of an entry path from the outermost inwards, e.g. `a` and `a/b` for `a/b/c.txt`.
+pub fn parent_directories(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(index, _)| &path[..index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("report.pdf").unwrap(), "report.pdf");
+        assert_eq!(normalize_path("./photos//2024\\a.jpg").unwrap(), "photos/2024/a.jpg");
+        assert_eq!(normalize_path("photos/./a.jpg/").unwrap(), "photos/a.jpg");
+        assert!(normalize_path("../etc/passwd").is_err());
+        assert!(normalize_path("photos/../../a.jpg").is_err());
+        assert!(normalize_path("/etc/passwd").is_err());
+        assert!(normalize_path("\\\\server\\share\\a.txt").is_err());
+        assert!(normalize_path("C:\\Users\\a.txt").is_err());
+        assert!(normalize_path("c:a.txt").is_err());
+        assert!(normalize_path("./").is_err());
+        assert!(normalize_path("").is_err());
+    }
+
+    #[test]
+    fn test_parent_directories() {
+        assert_eq!(parent_directories("a/b/c.txt").collect::<Vec<_>>(), vec!["a", "a/b"]);
+        assert_eq!(parent_directories("c.txt").count(), 0);
+    }
+}
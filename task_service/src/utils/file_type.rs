@@ -0,0 +1,47 @@
+/// Extensions of formats that are compressed already, compressing them again only costs time.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "mp3", "aac", "ogg", "mp4", "m4v", "mov", "mkv", "webm", "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar",
+];
+
+/// Magic bytes of compressed formats with their offset from the start of the file.
+const COMPRESSED_SIGNATURES: &[(usize, &[u8])] = &[
+    (0, b"\xFF\xD8\xFF"),       // jpeg
+    (0, b"\x89PNG\r\n\x1A\n"),  // png
+    (0, b"GIF8"),               // gif
+    (4, b"ftyp"),               // mp4, mov and other ISO media
+    (0, b"\x1A\x45\xDF\xA3"),   // mkv, webm
+    (0, b"PK\x03\x04"),         // zip and zip based documents
+    (0, b"\x1F\x8B"),           // gzip
+    (0, b"\xFD7zXZ\x00"),       // xz
+    (0, b"\x28\xB5\x2F\xFD"),   // zstd
+    (0, b"7z\xBC\xAF\x27\x1C"), // 7z
+    (0, b"Rar!\x1A\x07"),       // rar
+];
+
+/// Number of leading bytes `is_compressed` needs to recognize a format.
+pub const SIGNATURE_LENGTH: usize = 16;
+
+/// Tells whether a file is compressed already, by its extension or the magic bytes at the start of its content.
+pub fn is_compressed(filename: &str, head: &[u8]) -> bool {
+    let extension = filename.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    if extension.is_some_and(|extension| COMPRESSED_EXTENSIONS.contains(&extension.as_str())) {
+        return true;
+    }
+    COMPRESSED_SIGNATURES
+        .iter()
+        .any(|(offset, signature)| head.get(*offset..offset + signature.len()) == Some(*signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compressed() {
+        assert!(is_compressed("photo.JPG", b""));
+        assert!(is_compressed("photo", b"\xFF\xD8\xFF\xE0"));
+        assert!(is_compressed("clip.bin", b"\x00\x00\x00\x18ftypmp42"));
+        assert!(!is_compressed("notes.txt", b"plain text"));
+        assert!(!is_compressed("archive", b"PK"));
+    }
+}
@@ -1 +1,4 @@
-pub mod zip;
+pub mod archive_path;
+pub mod entry_path;
+pub mod file_type;
+pub mod password;
@@ -0,0 +1,81 @@
+use crate::settings::{PasswordConfig, PasswordGenerator};
+use rand::seq::SliceRandom;
+
+/// Generates an archive password as configured.
+pub fn generate_password(config: &PasswordConfig) -> String {
+    let mut rng = rand::thread_rng();
+    match config.generator {
+        PasswordGenerator::Random => {
+            let alphabet: Vec<char> = config.alphabet.chars().collect();
+            (0..config.length).filter_map(|_| alphabet.choose(&mut rng)).collect()
+        }
+        PasswordGenerator::Diceware => (0..config.length)
+            .filter_map(|_| eff_wordlist::large::LIST.choose(&mut rng).map(|(_, word)| *word))
+            .collect::<Vec<_>>()
+            .join(&config.separator),
+    }
+}
+
+/// Checks a password chosen by a caller against the policy.
+pub fn check_password(password: &str, config: &PasswordConfig) -> Result<(), String> {
+    if password.is_empty() {
+        return Err("Password must not be empty".into());
+    }
+    if password.chars().count() < config.min_length {
+        return Err(format!("Password must be at least {} characters long", config.min_length));
+    }
+    let lacks = |required: bool, is_member: fn(char) -> bool| required && !password.chars().any(is_member);
+    if lacks(config.require_lowercase, char::is_lowercase) {
+        return Err("Password must contain a lowercase letter".into());
+    }
+    if lacks(config.require_uppercase, char::is_uppercase) {
+        return Err("Password must contain an uppercase letter".into());
+    }
+    if lacks(config.require_digit, |c| c.is_ascii_digit()) {
+        return Err("Password must contain a digit".into());
+    }
+    if lacks(config.require_symbol, |c| !c.is_alphanumeric() && !c.is_whitespace()) {
+        return Err("Password must contain a symbol".into());
+    }
+    if config.banned.iter().any(|banned| banned.to_lowercase() == password.to_lowercase()) {
+        return Err("Password is not allowed".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_password() {
+        let mut config = PasswordConfig {
+            length: 8,
+            alphabet: "ab".to_owned(),
+            ..Default::default()
+        };
+        let password = generate_password(&config);
+        assert_eq!(password.len(), 8);
+        assert!(password.chars().all(|c| c == 'a' || c == 'b'));
+
+        config.generator = PasswordGenerator::Diceware;
+        config.length = 4;
+        assert_eq!(generate_password(&config).split('-').count(), 4);
+    }
+
+    #[test]
+    fn test_check_password() {
+        let config = PasswordConfig {
+            min_length: 8,
+            require_uppercase: true,
+            require_symbol: true,
+            banned: vec!["Passw0rd!".to_owned()],
+            ..Default::default()
+        };
+        assert!(check_password("Correct horse!", &config).is_ok());
+        assert!(check_password("Short!", &config).is_err());
+        assert!(check_password("no uppercase!", &config).is_err());
+        assert!(check_password("No symbols here", &config).is_err());
+        assert!(check_password("passw0rd!", &config).is_err());
+    }
+}